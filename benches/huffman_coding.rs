@@ -0,0 +1,56 @@
+use std::hint::black_box;
+use std::io::{self, Write};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use ripgzip::{inflate, BitSequence, BitWriter};
+
+const BLOCKS: usize = 1000;
+
+/// A DEFLATE stream of empty fixed-Huffman blocks, so decoding it is
+/// dominated by building the fixed literal/length table.
+fn empty_fixed_blocks() -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut writer = BitWriter::new(&mut data);
+    for i in 0..BLOCKS {
+        let is_final = u16::from(i + 1 == BLOCKS);
+        writer
+            .write_bits(BitSequence::new(0b010 | is_final, 3))
+            .unwrap();
+        // End of block, code 0000000.
+        writer.write_bits(BitSequence::new(0, 7)).unwrap();
+    }
+    writer.borrow_writer_from_boundary().unwrap();
+    data
+}
+
+fn fixed_tables(c: &mut Criterion) {
+    let data = empty_fixed_blocks();
+    c.bench_function("fixed_tables", |b| {
+        b.iter(|| inflate(black_box(data.as_slice()), io::sink()).unwrap())
+    });
+}
+
+/// Text flushed every 16 lines, so most of the decoding time goes into
+/// reading dynamic block headers.
+fn small_dynamic_blocks() -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+    for i in 0..BLOCKS {
+        for j in 0..16 {
+            writeln!(encoder, "line {:>5} word {:>5}", i, i * j % 997).unwrap();
+        }
+        encoder.flush().unwrap();
+    }
+    encoder.finish().unwrap()
+}
+
+fn dynamic_tables(c: &mut Criterion) {
+    let data = small_dynamic_blocks();
+    c.bench_function("dynamic_tables", |b| {
+        b.iter(|| inflate(black_box(data.as_slice()), io::sink()).unwrap())
+    });
+}
+
+criterion_group!(benches, fixed_tables, dynamic_tables);
+criterion_main!(benches);
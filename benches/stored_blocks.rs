@@ -0,0 +1,28 @@
+use std::hint::black_box;
+use std::io::{self, BufReader};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use ripgzip::{compress_stored, decompress};
+
+const LEN: usize = 4 << 20;
+
+fn stored_blocks(c: &mut Criterion) {
+    let input: Vec<u8> = (0..LEN).map(|i| (i % 251) as u8).collect();
+    let mut data = Vec::new();
+    compress_stored(input.as_slice(), &mut data).unwrap();
+
+    let mut group = c.benchmark_group("stored_blocks");
+    group.bench_function("slice", |b| {
+        b.iter(|| decompress(black_box(data.as_slice()), io::sink()).unwrap())
+    });
+    group.bench_function("small_buffer", |b| {
+        b.iter(|| {
+            let reader = BufReader::with_capacity(4096, black_box(data.as_slice()));
+            decompress(reader, io::sink()).unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, stored_blocks);
+criterion_main!(benches);
@@ -0,0 +1,40 @@
+use std::hint::black_box;
+use std::io::{self, Write};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use ripgzip::TrackingWriter;
+
+const MEMBERS: usize = 1000;
+const MEMBER: &[u8] = &[b'x'; 256];
+
+fn small_members(c: &mut Criterion) {
+    let mut group = c.benchmark_group("small_members");
+
+    group.bench_function("clear", |b| {
+        let mut writer = TrackingWriter::new(io::sink());
+        b.iter(|| {
+            for _ in 0..MEMBERS {
+                writer.write_all(black_box(MEMBER)).unwrap();
+                writer.write_previous(128, 128).unwrap();
+                black_box(writer.crc32());
+                writer.clear().unwrap();
+            }
+        })
+    });
+
+    group.bench_function("new_writer", |b| {
+        b.iter(|| {
+            for _ in 0..MEMBERS {
+                let mut writer = TrackingWriter::new(io::sink());
+                writer.write_all(black_box(MEMBER)).unwrap();
+                writer.write_previous(128, 128).unwrap();
+                black_box(writer.crc32());
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, small_members);
+criterion_main!(benches);
@@ -0,0 +1,205 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use anyhow::{bail, Context, Result};
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::decompress_one;
+use crate::gzip::{parse_extra_subfields, ID1, ID2};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// ID1 ID2 CM FLG MTIME XFL OS XLEN.
+const FIXED_HEADER_LEN: usize = 12;
+const BSIZE_ID: [u8; 2] = *b"BC";
+
+/// The empty block every BGZF file ends with (SAM/BAM spec, 4.1.2).
+const EOF_MARKER: [u8; 28] = [
+    31, 139, 8, 4, 0, 0, 0, 0, 0, 255, 6, 0, 66, 67, 2, 0, 27, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Random access into BGZF files (BAM, tabix-indexed VCF, ...).
+///
+/// BGZF is a series of small gzip members whose BC extra subfield records
+/// the member's compressed size. A virtual offset `coffset << 16 | uoffset`
+/// names the member starting at byte `coffset` and a position `uoffset`
+/// within its decoded data. Reading goes on into the following blocks.
+pub struct Bgzf<R> {
+    inner: R,
+    block: Vec<u8>,
+    position: usize,
+    block_offset: u64,
+    next_block_offset: u64,
+}
+
+impl<R: Read + Seek> Bgzf<R> {
+    /// Start reading at the beginning of `inner`.
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            block: vec![],
+            position: 0,
+            block_offset: 0,
+            next_block_offset: 0,
+        }
+    }
+
+    /// Virtual offset of the next byte [`Read::read`] returns.
+    pub fn virtual_offset(&self) -> u64 {
+        if self.position == self.block.len() {
+            self.next_block_offset << 16
+        } else {
+            self.block_offset << 16 | self.position as u64
+        }
+    }
+
+    /// Jump to a virtual offset, decoding the block it points into.
+    pub fn seek_virtual(&mut self, offset: u64) -> Result<()> {
+        let coffset = offset >> 16;
+        let uoffset = (offset & 0xffff) as usize;
+        self.inner.seek(SeekFrom::Start(coffset))?;
+        self.next_block_offset = coffset;
+        self.read_block()?;
+        if uoffset > self.block.len() {
+            bail!(
+                "virtual offset {:#x} is past the end of a {} byte block",
+                offset,
+                self.block.len()
+            );
+        }
+        self.position = uoffset;
+        Ok(())
+    }
+
+    /// Decode the block at `next_block_offset`. Returns `false` at the end
+    /// of the file.
+    fn read_block(&mut self) -> Result<bool> {
+        self.block.clear();
+        self.position = 0;
+        self.block_offset = self.next_block_offset;
+
+        let mut member = vec![0u8; FIXED_HEADER_LEN];
+        let read = self.inner.read(&mut member[..1])?;
+        if read == 0 {
+            return Ok(false);
+        }
+        self.inner
+            .read_exact(&mut member[1..])
+            .context("bgzf header")?;
+        if member[..2] != [ID1, ID2] {
+            bail!("not a gzip member at offset {}", self.block_offset);
+        }
+
+        let xlen = LittleEndian::read_u16(&member[10..]);
+        member.resize(FIXED_HEADER_LEN + usize::from(xlen), 0);
+        self.inner
+            .read_exact(&mut member[FIXED_HEADER_LEN..])
+            .context("bgzf extra field")?;
+        let bsize = parse_extra_subfields(&member[FIXED_HEADER_LEN..])?
+            .into_iter()
+            .find(|subfield| subfield.id == BSIZE_ID && subfield.data.len() == 2)
+            .map(|subfield| usize::from(LittleEndian::read_u16(&subfield.data)) + 1)
+            .context("not a BGZF block: no BC subfield")?;
+        if bsize < member.len() {
+            bail!("BGZF block size {} is smaller than its header", bsize);
+        }
+
+        let header_len = member.len();
+        member.resize(bsize, 0);
+        self.inner
+            .read_exact(&mut member[header_len..])
+            .context("bgzf block")?;
+        decompress_one(member.as_slice(), &mut self.block)?;
+        self.next_block_offset = self.block_offset + bsize as u64;
+        Ok(true)
+    }
+}
+
+impl<R: Read + Seek> Read for Bgzf<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Empty blocks, like the EOF marker, are skipped over.
+        while self.position == self.block.len() {
+            let more = self
+                .read_block()
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+            if !more {
+                return Ok(0);
+            }
+        }
+        let read = (&self.block[self.position..]).read(buf)?;
+        self.position += read;
+        Ok(read)
+    }
+}
+
+/// Whether `input` ends with the BGZF EOF marker. Writers append it last, so
+/// a file without it was most likely cut short. The stream position is left
+/// where it was.
+pub fn has_bgzf_eof_marker<R: Read + Seek>(input: &mut R) -> Result<bool> {
+    let position = input.stream_position()?;
+    let len = input.seek(SeekFrom::End(0))?;
+    let mut found = false;
+    if len >= EOF_MARKER.len() as u64 {
+        let mut tail = [0u8; EOF_MARKER.len()];
+        input.seek(SeekFrom::End(-(EOF_MARKER.len() as i64)))?;
+        input.read_exact(&mut tail)?;
+        found = tail == EOF_MARKER;
+    }
+    input.seek(SeekFrom::Start(position))?;
+    Ok(found)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::BGZF;
+    use std::io::Cursor;
+
+    #[test]
+    fn read_all() -> Result<()> {
+        let mut output = String::new();
+        Bgzf::new(Cursor::new(BGZF)).read_to_string(&mut output)?;
+        assert_eq!(
+            output,
+            "hello bgzf block one\n".repeat(3) + &"second block\n".repeat(2)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn seek_virtual() -> Result<()> {
+        let mut reader = Bgzf::new(Cursor::new(BGZF));
+        reader.seek_virtual(52 << 16 | 7)?;
+        let mut output = String::new();
+        reader.read_to_string(&mut output)?;
+        assert_eq!(output, "block\nsecond block\n");
+
+        reader.seek_virtual(6)?;
+        let mut word = [0u8; 4];
+        reader.read_exact(&mut word)?;
+        assert_eq!(&word, b"bgzf");
+        assert_eq!(reader.virtual_offset(), 10);
+
+        assert!(reader.seek_virtual(52 << 16 | 27).is_err());
+        assert!(reader.seek_virtual(1 << 16).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn eof_marker() -> Result<()> {
+        let mut input = Cursor::new(BGZF);
+        input.set_position(5);
+        assert!(has_bgzf_eof_marker(&mut input)?);
+        assert_eq!(input.position(), 5);
+
+        let truncated = &BGZF[..BGZF.len() - EOF_MARKER.len()];
+        assert!(!has_bgzf_eof_marker(&mut Cursor::new(truncated))?);
+        assert!(!has_bgzf_eof_marker(&mut Cursor::new(&BGZF[..10]))?);
+        Ok(())
+    }
+}
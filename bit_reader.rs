@@ -12,10 +12,27 @@ pub struct BitSequence {
 }
 
 impl BitSequence {
-    pub fn new(bits: u16, len: u8) -> Self {
+    /// `len` must be at most 16; debug builds check it. Use
+    /// [`try_new`](Self::try_new) for lengths that may not fit.
+    pub const fn new(bits: u16, len: u8) -> Self {
+        debug_assert!(len <= 16, "bit sequence longer than 16 bits");
         Self { bits, len }
     }
 
+    /// Like [`new`](Self::new), or `None` if `len` is over 16.
+    pub const fn try_new(bits: u16, len: u8) -> Option<Self> {
+        if len > 16 {
+            return None;
+        }
+        Some(Self { bits, len })
+    }
+
+    /// Build a sequence from `bits` given most significant bit first, the way
+    /// Huffman codes are written down, in the LSB-first order `read_bits` uses.
+    pub fn from_msb(bits: u16, len: u8) -> Self {
+        Self::new(bits, len).reverse()
+    }
+
     pub fn bits(&self) -> u16 {
         self.bits
     }
@@ -24,12 +41,41 @@ impl BitSequence {
         self.len
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reverse the order of the low `len` bits.
+    pub fn reverse(self) -> Self {
+        if self.len == 0 {
+            return self;
+        }
+        Self {
+            bits: self.bits.reverse_bits() >> (16 - self.len),
+            len: self.len,
+        }
+    }
+
+    /// Append `other` after `self`. The result must fit in 16 bits.
     pub fn concat(self, other: Self) -> Self {
+        debug_assert!(
+            u16::from(self.len) + u16::from(other.len) <= 16,
+            "concatenated bit sequence longer than 16 bits"
+        );
         Self {
             bits: (self.bits << other.len) + other.bits,
             len: self.len + other.len,
         }
     }
+
+    /// Like [`BitSequence::concat`], but `None` if the result wouldn't fit
+    /// in 16 bits.
+    pub fn checked_concat(self, other: Self) -> Option<Self> {
+        if u16::from(self.len) + u16::from(other.len) > 16 {
+            return None;
+        }
+        Some(self.concat(other))
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -37,6 +83,7 @@ impl BitSequence {
 pub struct BitReader<T> {
     stream: T,
     bit_sequence: BitSequence,
+    position: u64,
 }
 
 impl<T: BufRead> BitReader<T> {
@@ -44,10 +91,45 @@ impl<T: BufRead> BitReader<T> {
         Self {
             stream,
             bit_sequence: BitSequence::new(0, 0),
+            position: 0,
         }
     }
 
+    /// Number of bits consumed so far, counting the padding skipped by
+    /// [`BitReader::borrow_reader_from_boundary`] and anything reported with
+    /// [`BitReader::advance`].
+    pub fn bit_position(&self) -> u64 {
+        self.position
+    }
+
+    /// Bits read from the stream but not consumed yet.
+    pub fn buffered_bits(&self) -> u8 {
+        self.bit_sequence.len
+    }
+
+    /// The bits [`BitReader::buffered_bits`] counts.
+    pub(crate) fn buffered(&self) -> BitSequence {
+        self.bit_sequence
+    }
+
+    /// Continue after a reader that had `buffered` left over and had
+    /// consumed `position` bits.
+    pub(crate) fn restore(&mut self, buffered: BitSequence, position: u64) {
+        self.bit_sequence = buffered;
+        self.position = position;
+    }
+
+    /// Account for `bytes` read straight from the stream returned by
+    /// [`BitReader::borrow_reader_from_boundary`].
+    pub fn advance(&mut self, bytes: usize) {
+        self.position += 8 * bytes as u64;
+    }
+
     pub fn read_bits(&mut self, len: u8) -> io::Result<BitSequence> {
+        // Extra-bit fields can be zero bits wide; those must not touch the stream.
+        if len == 0 {
+            return Ok(BitSequence::new(0, 0));
+        }
         let mut already_len: u8 = self.bit_sequence.len();
         let mut bit_sequence: u32 = self.bit_sequence.bits().into();
         while already_len < len {
@@ -57,10 +139,38 @@ impl<T: BufRead> BitReader<T> {
         }
         let ans: u16 = (bit_sequence & ((1 << len) - 1)) as u16;
         self.bit_sequence = BitSequence::new((bit_sequence >> len) as u16, already_len - len);
+        self.position += u64::from(len);
         Ok(BitSequence::new(ans, len))
     }
 
+    /// Whether the stream is exhausted, leaving at most the padding bits of
+    /// its last byte.
+    pub fn only_padding_left(&mut self) -> io::Result<bool> {
+        Ok(self.stream.fill_buf()?.is_empty())
+    }
+
+    /// Read a single bit, e.g. a flag like BFINAL.
+    pub fn read_bool(&mut self) -> io::Result<bool> {
+        Ok(self.read_bits(1)?.bits() != 0)
+    }
+
+    /// Skip to the next byte boundary and read a whole byte.
+    pub fn read_u8_aligned(&mut self) -> io::Result<u8> {
+        let byte = self.borrow_reader_from_boundary().read_u8()?;
+        self.position += 8;
+        Ok(byte)
+    }
+
+    /// Swap in a new stream, dropping any buffered bits.
+    /// Returns the previous stream.
+    pub fn reset(&mut self, stream: T) -> T {
+        self.bit_sequence = BitSequence::new(0, 0);
+        self.position = 0;
+        std::mem::replace(&mut self.stream, stream)
+    }
+
     pub fn borrow_reader_from_boundary(&mut self) -> &mut T {
+        self.position += u64::from(self.bit_sequence.len);
         self.bit_sequence.len = 0;
         self.bit_sequence.bits = 0;
         &mut self.stream
@@ -74,6 +184,58 @@ mod tests {
     use super::*;
     use byteorder::ReadBytesExt;
 
+    #[test]
+    fn checked_concat() {
+        let a = BitSequence::new(0b101, 3);
+        assert_eq!(
+            a.checked_concat(BitSequence::new(0b1, 1)),
+            Some(BitSequence::new(0b1011, 4))
+        );
+        assert_eq!(
+            a.checked_concat(BitSequence::new(0, 13)),
+            Some(BitSequence::new(0b101 << 13, 16))
+        );
+        assert_eq!(a.checked_concat(BitSequence::new(0, 14)), None);
+        assert_eq!(a.checked_concat(BitSequence::new(0, 16)), None);
+    }
+
+    #[test]
+    fn reverse() {
+        assert_eq!(
+            BitSequence::new(0b1101, 4).reverse(),
+            BitSequence::new(0b1011, 4)
+        );
+        assert_eq!(
+            BitSequence::new(0b110, 5).reverse(),
+            BitSequence::new(0b01100, 5)
+        );
+        assert_eq!(
+            BitSequence::new(1, 16).reverse(),
+            BitSequence::new(0x8000, 16)
+        );
+        assert_eq!(BitSequence::new(0, 0).reverse(), BitSequence::new(0, 0));
+        assert_eq!(
+            BitSequence::from_msb(0b0011_0000, 8),
+            BitSequence::new(0b0000_1100, 8)
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "bit sequence longer than 16 bits")]
+    fn longer_than_16_bits() {
+        BitSequence::from_msb(1, 17);
+    }
+
+    #[test]
+    fn try_new() {
+        assert_eq!(
+            BitSequence::try_new(0b101, 16),
+            Some(BitSequence::new(0b101, 16))
+        );
+        assert_eq!(BitSequence::try_new(0b101, 17), None);
+    }
+
     #[test]
     fn read_bits() -> io::Result<()> {
         let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111];
@@ -91,12 +253,63 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn read_zero_bits() -> io::Result<()> {
+        let mut reader = BitReader::new(&[][..]);
+        assert_eq!(reader.read_bits(0)?, BitSequence::new(0, 0));
+        assert_eq!(reader.bit_position(), 0);
+
+        let data: &[u8] = &[0b01100011];
+        let mut reader = BitReader::new(data);
+        assert_eq!(reader.read_bits(3)?, BitSequence::new(0b011, 3));
+        assert_eq!(reader.read_bits(0)?, BitSequence::new(0, 0));
+        assert_eq!(reader.read_bits(5)?, BitSequence::new(0b01100, 5));
+        assert_eq!(reader.bit_position(), 8);
+        Ok(())
+    }
+
+    #[test]
+    fn read_bool_and_u8_aligned() -> io::Result<()> {
+        let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111];
+        let mut reader = BitReader::new(data);
+        assert!(reader.read_bool()?);
+        assert!(reader.read_bool()?);
+        assert!(!reader.read_bool()?);
+        assert_eq!(reader.read_u8_aligned()?, 0b11011011);
+        assert_eq!(reader.bit_position(), 16);
+        assert_eq!(reader.read_u8_aligned()?, 0b10101111);
+        assert!(reader.read_u8_aligned().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn only_padding_left() -> io::Result<()> {
+        let mut reader = BitReader::new(&[0b01100011, 0b11011011][..]);
+        assert!(!reader.only_padding_left()?);
+        reader.read_bits(9)?;
+        assert!(reader.only_padding_left()?);
+        Ok(())
+    }
+
     #[test]
     fn borrow_reader_from_boundary() -> io::Result<()> {
         let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111];
         let mut reader = BitReader::new(data);
         assert_eq!(reader.read_bits(3)?, BitSequence::new(0b011, 3));
         assert_eq!(reader.borrow_reader_from_boundary().read_u8()?, 0b11011011);
+        assert_eq!(reader.bit_position(), 8);
+        reader.advance(1);
+        assert_eq!(reader.read_bits(8)?, BitSequence::new(0b10101111, 8));
+        assert_eq!(reader.bit_position(), 24);
+        Ok(())
+    }
+
+    #[test]
+    fn reset() -> io::Result<()> {
+        let mut reader = BitReader::new(&[0b01100011, 0b11011011][..]);
+        assert_eq!(reader.read_bits(3)?, BitSequence::new(0b011, 3));
+        let old = reader.reset(&[0b10101111][..]);
+        assert_eq!(old, &[0b11011011]);
         assert_eq!(reader.read_bits(8)?, BitSequence::new(0b10101111, 8));
         Ok(())
     }
@@ -0,0 +1,77 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, Write};
+
+use byteorder::WriteBytesExt;
+
+use crate::bit_reader::BitSequence;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Counterpart of [`BitReader`](crate::BitReader): packs bit sequences into
+/// bytes, least significant bit first.
+pub struct BitWriter<T> {
+    stream: T,
+    bits: u32,
+    len: u8,
+}
+
+impl<T: Write> BitWriter<T> {
+    pub fn new(stream: T) -> Self {
+        Self {
+            stream,
+            bits: 0,
+            len: 0,
+        }
+    }
+
+    pub fn write_bits(&mut self, sequence: BitSequence) -> io::Result<()> {
+        let mask = (1u32 << sequence.len()) - 1;
+        self.bits |= (u32::from(sequence.bits()) & mask) << self.len;
+        self.len += sequence.len();
+        while self.len >= 8 {
+            self.stream.write_u8(self.bits as u8)?;
+            self.bits >>= 8;
+            self.len -= 8;
+        }
+        Ok(())
+    }
+
+    /// Pad with zero bits up to the next byte boundary and hand out the stream.
+    pub fn borrow_writer_from_boundary(&mut self) -> io::Result<&mut T> {
+        if self.len > 0 {
+            self.stream.write_u8(self.bits as u8)?;
+            self.bits = 0;
+            self.len = 0;
+        }
+        Ok(&mut self.stream)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_reader::BitReader;
+
+    #[test]
+    fn write_bits() -> io::Result<()> {
+        let mut data = Vec::new();
+        let mut writer = BitWriter::new(&mut data);
+        writer.write_bits(BitSequence::new(0b1, 1))?;
+        writer.write_bits(BitSequence::new(0b01, 2))?;
+        writer.write_bits(BitSequence::new(0b100, 3))?;
+        writer.write_bits(BitSequence::new(0b1101, 4))?;
+        writer.write_bits(BitSequence::new(0b10110, 5))?;
+        writer.write_bits(BitSequence::new(0b01011111, 8))?;
+        writer.write_bits(BitSequence::new(0b1, 1))?;
+        writer.write_bits(BitSequence::new(0b1, 1))?;
+        writer.borrow_writer_from_boundary()?.write_u8(0xaa)?;
+        assert_eq!(data, [0b01100011, 0b11011011, 0b10101111, 0b1, 0xaa]);
+
+        let mut reader = BitReader::new(data.as_slice());
+        assert_eq!(reader.read_bits(6)?, BitSequence::new(0b100011, 6));
+        Ok(())
+    }
+}
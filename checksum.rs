@@ -0,0 +1,215 @@
+#![forbid(unsafe_code)]
+
+use std::fmt;
+
+use crc::{Crc, Digest, CRC_32_ISO_HDLC};
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub const ALGORITHM: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Running checksum over the decoded data.
+pub trait Checksum {
+    fn update(&mut self, bytes: &[u8]);
+    fn finalize(&self) -> u32;
+    /// Start over as if nothing was fed in.
+    fn reset(&mut self);
+    /// Continue from a checksum that `finalize` returned `value`.
+    fn resume(&mut self, value: u32);
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// CRC-32/ISO-HDLC, as used by gzip, unless built
+/// [`with_algorithm`](Crc32::with_algorithm). `finalize` leaves the running
+/// digest alone by finalizing a copy of it, which is only a table reference
+/// and a 32-bit register.
+#[derive(Clone)]
+pub struct Crc32 {
+    algorithm: &'static Crc<u32>,
+    digest: Digest<'static, u32>,
+}
+
+impl Crc32 {
+    /// Use another 32-bit CRC, for gzip variants that don't use the standard
+    /// polynomial, e.g. `static BZIP2: Crc<u32> = Crc::<u32>::new(&CRC_32_BZIP2)`.
+    pub fn with_algorithm(algorithm: &'static Crc<u32>) -> Self {
+        Self {
+            algorithm,
+            digest: algorithm.digest(),
+        }
+    }
+
+    /// The final value, consuming the digest instead of finalizing a copy.
+    pub fn into_value(self) -> u32 {
+        self.digest.finalize()
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::with_algorithm(&ALGORITHM)
+    }
+}
+
+impl fmt::Debug for Crc32 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Crc32")
+            .field("algorithm", self.algorithm.algorithm)
+            .field("value", &self.finalize())
+            .finish()
+    }
+}
+
+impl Checksum for Crc32 {
+    fn update(&mut self, bytes: &[u8]) {
+        self.digest.update(bytes);
+    }
+
+    fn finalize(&self) -> u32 {
+        self.digest.clone().finalize()
+    }
+
+    fn reset(&mut self) {
+        self.digest = self.algorithm.digest();
+    }
+
+    fn resume(&mut self, value: u32) {
+        // Undo the steps of `finalize`, then those `digest_with_initial`
+        // applies to its argument.
+        let algorithm = self.algorithm.algorithm;
+        let unused = 32 - algorithm.width;
+        let mut register = value ^ algorithm.xorout;
+        if !algorithm.refout {
+            register <<= unused;
+        }
+        if algorithm.refin != algorithm.refout {
+            register = register.reverse_bits();
+        }
+        let initial = if algorithm.refin {
+            (register << unused).reverse_bits()
+        } else {
+            register >> unused
+        };
+        self.digest = self.algorithm.digest_with_initial(initial);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+const ADLER_MOD: u32 = 65521;
+// Largest n such that 255 * n * (n + 1) / 2 + (n + 1) * (ADLER_MOD - 1) fits in u32.
+const ADLER_NMAX: usize = 5552;
+
+/// Adler-32, as used by zlib.
+#[derive(Clone, Copy, Debug)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Self { a: 1, b: 0 }
+    }
+}
+
+impl Checksum for Adler32 {
+    fn update(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(ADLER_NMAX) {
+            for &byte in chunk {
+                self.a += u32::from(byte);
+                self.b += self.a;
+            }
+            self.a %= ADLER_MOD;
+            self.b %= ADLER_MOD;
+        }
+    }
+
+    fn finalize(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    fn resume(&mut self, value: u32) {
+        self.a = value & 0xffff;
+        self.b = value >> 16;
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Skips checksumming entirely, e.g. for raw DEFLATE.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoChecksum;
+
+impl Checksum for NoChecksum {
+    fn update(&mut self, _bytes: &[u8]) {}
+
+    fn finalize(&self) -> u32 {
+        0
+    }
+
+    fn reset(&mut self) {}
+
+    fn resume(&mut self, _value: u32) {}
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32() {
+        let mut checksum = Crc32::default();
+        checksum.update(b"1234");
+        checksum.update(b"56789");
+        assert_eq!(checksum.finalize(), 0xcbf43926);
+        checksum.reset();
+        assert_eq!(checksum.finalize(), 0);
+    }
+
+    #[test]
+    fn other_algorithm() {
+        static BZIP2: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_BZIP2);
+        let mut checksum = Crc32::with_algorithm(&BZIP2);
+        checksum.update(b"123456789");
+        assert_eq!(checksum.finalize(), 0xfc891918);
+        checksum.reset();
+        checksum.update(b"123456789");
+        assert_eq!(checksum.finalize(), 0xfc891918);
+    }
+
+    #[test]
+    fn resume() {
+        static BZIP2: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_BZIP2);
+        fn check<C: Checksum>(mut first: C, mut second: C) {
+            first.update(b"1234");
+            second.resume(first.finalize());
+            first.update(b"56789");
+            second.update(b"56789");
+            assert_eq!(second.finalize(), first.finalize());
+        }
+        check(Crc32::default(), Crc32::default());
+        check(Crc32::with_algorithm(&BZIP2), Crc32::with_algorithm(&BZIP2));
+        check(Adler32::default(), Adler32::default());
+    }
+
+    #[test]
+    fn adler32() {
+        let mut checksum = Adler32::default();
+        assert_eq!(checksum.finalize(), 1);
+        checksum.update(b"Wiki");
+        checksum.update(b"pedia");
+        assert_eq!(checksum.finalize(), 0x11e60398);
+
+        checksum.reset();
+        checksum.update(&[0xff; 100_000]);
+        assert_eq!(checksum.finalize(), 0x149a_302c);
+    }
+}
@@ -0,0 +1,114 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, BufRead, Write};
+
+use anyhow::{ensure, Result};
+
+use crate::{decompress_with, DecodeOptions, FlushPolicy};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Decode a gzip stream, handing the output to `sink` in slices of at most
+/// `chunk_size` bytes. Only one chunk is buffered at a time. An error from
+/// `sink` stops decoding and is returned as is.
+pub fn decompress_chunked<R, F>(input: R, chunk_size: usize, sink: F) -> Result<()>
+where
+    R: BufRead,
+    F: FnMut(&[u8]) -> Result<()>,
+{
+    ensure!(chunk_size > 0, "chunk size must be positive");
+    let mut writer = ChunkWriter {
+        buffer: Vec::with_capacity(chunk_size),
+        chunk_size,
+        sink,
+        error: None,
+    };
+    // Flushing emits a short chunk, so leave it to the very end.
+    let options = DecodeOptions::builder()
+        .flush_every(FlushPolicy::AtEnd)
+        .build();
+    let result = decompress_with(options, input, &mut writer).and_then(|_| Ok(writer.flush()?));
+    match writer.error.take() {
+        Some(error) => Err(error),
+        None => result.map(|_| ()),
+    }
+}
+
+/// Buffers writes and passes full chunks on to the callback. The callback's
+/// error is kept aside so it survives the trip through `io::Error`.
+struct ChunkWriter<F> {
+    buffer: Vec<u8>,
+    chunk_size: usize,
+    sink: F,
+    error: Option<anyhow::Error>,
+}
+
+impl<F: FnMut(&[u8]) -> Result<()>> ChunkWriter<F> {
+    fn emit(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let result = (self.sink)(&self.buffer);
+        self.buffer.clear();
+        result.map_err(|error| {
+            self.error = Some(error);
+            io::Error::other("chunk sink failed")
+        })
+    }
+}
+
+impl<F: FnMut(&[u8]) -> Result<()>> Write for ChunkWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.chunk_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() == self.chunk_size {
+            self.emit()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.emit()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompress;
+    use crate::test_data::{FIRST, SECOND};
+    use anyhow::bail;
+
+    #[test]
+    fn chunks() -> Result<()> {
+        let data = [FIRST, SECOND].concat();
+        let mut expected = Vec::new();
+        decompress(data.as_slice(), &mut expected)?;
+
+        let mut chunks = Vec::new();
+        decompress_chunked(data.as_slice(), 7, |chunk| {
+            chunks.push(chunk.to_vec());
+            Ok(())
+        })?;
+        // Only the last chunk is short, even across the member boundary.
+        let (last, full) = chunks.split_last().unwrap();
+        assert!(full.iter().all(|chunk| chunk.len() == 7));
+        assert!(!last.is_empty() && last.len() <= 7);
+        assert_eq!(chunks.concat(), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn sink_error_aborts() {
+        let mut calls = 0;
+        let error = decompress_chunked(FIRST, 4, |_| {
+            calls += 1;
+            bail!("receiver hung up")
+        })
+        .unwrap_err();
+        assert_eq!(calls, 1);
+        assert_eq!(error.to_string(), "receiver hung up");
+    }
+}
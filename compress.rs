@@ -0,0 +1,184 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, Read, Write};
+
+use anyhow::{Context, Result};
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use crate::bit_reader::BitSequence;
+use crate::bit_writer::BitWriter;
+use crate::gzip::{ID1, ID2};
+use crate::huffman_coding::{fixed_litlen_lengths, HuffmanEncoder};
+use crate::tracking_writer::TrackingWriter;
+
+////////////////////////////////////////////////////////////////////////////////
+
+const CM_DEFLATE: u8 = 8;
+const OS_UNKNOWN: u8 = 255;
+
+const MAX_STORED_LEN: usize = u16::MAX as usize;
+const READ_BUFFER_LEN: usize = 1 << 16;
+
+const END_OF_BLOCK: u16 = 256;
+/// BFINAL = 1, BTYPE = 01.
+const FINAL_FIXED_BLOCK: BitSequence = BitSequence::new(0b011, 3);
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Wrap `input` in a single gzip member made of stored DEFLATE blocks only.
+/// Nothing gets smaller, but the output is valid gzip that any decoder accepts.
+pub fn compress_stored<R: Read, W: Write>(mut input: R, mut output: W) -> Result<()> {
+    write_header(&mut output)?;
+
+    let mut tracker = TrackingWriter::new(io::sink());
+    let mut block = vec![0; MAX_STORED_LEN];
+    let mut next = vec![0; MAX_STORED_LEN];
+    let mut len = fill(&mut input, &mut block)?;
+    loop {
+        // A full block may be followed by more data; only the next read tells.
+        let next_len = if len == MAX_STORED_LEN {
+            fill(&mut input, &mut next)?
+        } else {
+            0
+        };
+        let is_final = next_len == 0;
+
+        output.write_u8(u8::from(is_final))?;
+        output.write_u16::<LittleEndian>(len as u16)?;
+        output.write_u16::<LittleEndian>(!(len as u16))?;
+        output
+            .write_all(&block[..len])
+            .context("stored block write")?;
+        tracker.write_all(&block[..len])?;
+
+        if is_final {
+            break;
+        }
+        std::mem::swap(&mut block, &mut next);
+        len = next_len;
+    }
+
+    write_footer(&mut output, &mut tracker)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Compress `input` into a single gzip member holding one fixed-Huffman
+/// block. Every byte is coded as a literal; there is no match finding yet.
+pub fn compress_fixed<R: Read, W: Write>(mut input: R, mut output: W) -> Result<()> {
+    write_header(&mut output)?;
+
+    let encoder = HuffmanEncoder::from_lengths(&fixed_litlen_lengths())?;
+    let mut tracker = TrackingWriter::new(io::sink());
+    let mut writer = BitWriter::new(&mut output);
+    writer.write_bits(FINAL_FIXED_BLOCK)?;
+
+    let mut buffer = vec![0; READ_BUFFER_LEN];
+    loop {
+        let len = fill(&mut input, &mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        for &byte in &buffer[..len] {
+            write_symbol(&mut writer, &encoder, byte.into())?;
+        }
+        tracker.write_all(&buffer[..len])?;
+    }
+    write_symbol(&mut writer, &encoder, END_OF_BLOCK)?;
+
+    write_footer(writer.borrow_writer_from_boundary()?, &mut tracker)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Huffman codes go out most significant bit first.
+fn write_symbol<W: Write>(
+    writer: &mut BitWriter<W>,
+    encoder: &HuffmanEncoder,
+    symbol: u16,
+) -> Result<()> {
+    let code = encoder.encode(symbol).context("symbol has no code")?;
+    writer.write_bits(code.reverse())?;
+    Ok(())
+}
+
+fn write_header<W: Write>(output: &mut W) -> Result<()> {
+    output
+        .write_all(&[ID1, ID2, CM_DEFLATE, 0, 0, 0, 0, 0, 0, OS_UNKNOWN])
+        .context("header write")?;
+    Ok(())
+}
+
+fn write_footer<W: Write>(output: &mut W, tracker: &mut TrackingWriter<io::Sink>) -> Result<()> {
+    output.write_u32::<LittleEndian>(tracker.crc32())?;
+    output.write_u32::<LittleEndian>(tracker.byte_count())?;
+    Ok(())
+}
+
+/// Read until `buffer` is full or the input ends.
+fn fill<R: Read>(input: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match input.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(filled)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::CompressionType;
+    use crate::{decompress, inflate_blocks};
+
+    fn roundtrip(data: &[u8]) -> Result<Vec<u8>> {
+        let mut compressed = Vec::new();
+        super::compress_stored(data, &mut compressed)?;
+        let mut output = Vec::new();
+        assert_eq!(decompress(compressed.as_slice(), &mut output)?, 1);
+        assert_eq!(output, data);
+        Ok(compressed)
+    }
+
+    #[test]
+    fn compress_stored() -> Result<()> {
+        assert_eq!(roundtrip(b"")?.len(), 10 + 5 + 8);
+        roundtrip(b"hello, stored world\n")?;
+
+        let data = (0..150_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let compressed = roundtrip(&data)?;
+        let blocks = inflate_blocks(&compressed[10..]).collect::<Result<Vec<_>>>()?;
+        assert_eq!(blocks.len(), 3);
+        assert!(blocks[2].is_final);
+
+        let compressed = roundtrip(&data[..2 * MAX_STORED_LEN])?;
+        let blocks = inflate_blocks(&compressed[10..]).collect::<Result<Vec<_>>>()?;
+        assert_eq!(blocks.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn compress_fixed() -> Result<()> {
+        let data = (0..100_000)
+            .map(|i| (i * 7 % 256) as u8)
+            .collect::<Vec<_>>();
+        for data in [&b""[..], b"a", b"hello, fixed huffman\n", &data] {
+            let mut compressed = Vec::new();
+            super::compress_fixed(data, &mut compressed)?;
+            let mut output = Vec::new();
+            assert_eq!(decompress(compressed.as_slice(), &mut output)?, 1);
+            assert_eq!(output, data);
+
+            let blocks = inflate_blocks(&compressed[10..]).collect::<Result<Vec<_>>>()?;
+            assert_eq!(blocks.len(), 1);
+            assert_eq!(blocks[0].compression_type, CompressionType::FixedTree);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,402 @@
+#![forbid(unsafe_code)]
+
+use std::io::{BufRead, Write};
+
+use anyhow::{bail, Result};
+
+use crate::bit_reader::BitReader;
+use crate::deflate::DeflateReader;
+use crate::error::{DecodeError, DecodeOffset};
+use crate::gzip::{GzipReader, MemberFooter, MemberHeader};
+use crate::options::{DecodeOptions, UnknownOsPolicy};
+use crate::report::{DecodeStats, Decoded, MemberFailure, Warning};
+use crate::tracking_reader::TrackingReader;
+use crate::tracking_writer::TrackingWriter;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A gzip decode in progress, one member at a time. [`decompress_with`]
+/// runs one of these to the end; use it directly to look at each member as
+/// it comes or to stop early.
+///
+/// [`decompress_with`]: crate::decompress_with
+pub struct Decoder<R, W> {
+    deflate: DeflateReader<TrackingReader<R>, TrackingWriter<W>>,
+    options: DecodeOptions,
+    decoded: Decoded,
+    stats: DecodeStats,
+    latest_mtime: u64,
+    index: usize,
+    magic_consumed: bool,
+    done: bool,
+}
+
+impl<R: BufRead, W: Write> Decoder<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self::with_options(DecodeOptions::default(), input, output)
+    }
+
+    pub fn with_options(options: DecodeOptions, input: R, output: W) -> Self {
+        let mut deflate = DeflateReader::new(
+            BitReader::new(TrackingReader::new(input)),
+            TrackingWriter::with_checksum(output, options.crc32.clone()),
+        );
+        deflate.set_output_limit(options.max_output);
+        deflate.set_flush_policy(options.flush);
+        Self {
+            deflate,
+            latest_mtime: options.latest_mtime(),
+            options,
+            decoded: Decoded::default(),
+            stats: DecodeStats::default(),
+            index: 0,
+            magic_consumed: false,
+            done: false,
+        }
+    }
+
+    pub fn options(&self) -> &DecodeOptions {
+        &self.options
+    }
+
+    /// What was decoded so far.
+    pub fn decoded(&self) -> &Decoded {
+        &self.decoded
+    }
+
+    pub fn into_decoded(self) -> Decoded {
+        self.decoded
+    }
+
+    /// Block statistics so far. `input_bytes` is filled in once the stream
+    /// is over.
+    pub fn stats(&self) -> &DecodeStats {
+        &self.stats
+    }
+
+    pub fn get_output(&mut self) -> &mut W {
+        self.deflate.get_output()
+    }
+
+    /// Decode the next member and return its header, or `None` once the
+    /// stream is over and the output flushed. In lenient mode a failing
+    /// member is recorded in [`Decoded::failed_members`] and the one after
+    /// it is decoded instead. Don't call this again after an error.
+    pub fn decode_one(&mut self) -> Result<Option<MemberHeader>> {
+        self.decode_one_with(|_, _| Ok(()))
+    }
+
+    /// Like [`decode_one`](Self::decode_one), calling `before_body` with
+    /// each member's header and the output writer once the header passed
+    /// its checks, e.g. to swap in a writer for this member.
+    pub fn decode_one_with(
+        &mut self,
+        mut before_body: impl FnMut(&MemberHeader, &mut W) -> Result<()>,
+    ) -> Result<Option<MemberHeader>> {
+        while !self.done {
+            if !self.at_member()? {
+                break;
+            }
+            if let Some(limit) = self
+                .options
+                .max_members
+                .filter(|&limit| self.index >= limit)
+            {
+                bail!(DecodeError::TooManyMembers { limit });
+            }
+            let index = self.index;
+            self.index += 1;
+            if let Some(header) = self.decode_member(index, &mut before_body)? {
+                return Ok(Some(header));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Decode the remaining members.
+    pub fn decode_all(&mut self) -> Result<&Decoded> {
+        while self.decode_one()?.is_some() {}
+        Ok(&self.decoded)
+    }
+
+    /// Whether another member follows, finishing the stream if not. Fails
+    /// on trailing garbage in strict mode.
+    fn at_member(&mut self) -> Result<bool> {
+        if self.magic_consumed {
+            return Ok(true);
+        }
+        let offset = self.deflate.get_input().byte_count();
+        let mut gzip_reader = GzipReader::new(self.deflate.get_input());
+        if gzip_reader.is_empty()? {
+            self.finish(offset)?;
+            return Ok(false);
+        }
+        if self.index == 0 || self.options.lenient {
+            return Ok(true);
+        }
+        // Consumed rather than peeked, so a magic split across reads counts.
+        if gzip_reader.read_magic()? {
+            self.magic_consumed = true;
+            return Ok(true);
+        }
+        if self.options.strict {
+            bail!(DecodeError::TrailingGarbage { offset });
+        }
+        self.finish(offset)?;
+        Ok(false)
+    }
+
+    /// Returns the header, or `None` if the member failed in lenient mode.
+    fn decode_member(
+        &mut self,
+        index: usize,
+        before_body: &mut impl FnMut(&MemberHeader, &mut W) -> Result<()>,
+    ) -> Result<Option<MemberHeader>> {
+        let result = self.read_member(index, before_body);
+        self.magic_consumed = false;
+        let warning = match result {
+            Ok(footer) => {
+                self.decoded.footers.push(footer);
+                None
+            }
+            Err(error) => match footer_mismatch(&error) {
+                Some(error) if self.options.ignore_checksum => Some(Warning::FooterMismatch {
+                    member: index,
+                    error,
+                }),
+                mismatch if self.options.lenient => {
+                    let error = error.context(decode_offset(&mut self.deflate));
+                    self.decoded
+                        .failed_members
+                        .push(MemberFailure { index, error });
+                    // A footer mismatch comes out of finish_member, which
+                    // already finished the output.
+                    if mismatch.is_none() {
+                        self.deflate.output()?;
+                    }
+                    self.magic_consumed =
+                        GzipReader::new(self.deflate.get_input()).skip_to_magic()?;
+                    if !self.magic_consumed {
+                        let offset = self.deflate.get_input().byte_count();
+                        self.finish(offset)?;
+                    }
+                    return Ok(None);
+                }
+                _ => {
+                    let error = error.context(decode_offset(&mut self.deflate));
+                    return Err(error.context(format!("gzip member {}", index)));
+                }
+            },
+        };
+        self.decoded.warnings.extend(warning);
+        self.decoded.members += 1;
+        self.stats.members += 1;
+        Ok(self.decoded.headers.last().cloned())
+    }
+
+    /// Header, checks, blocks and footer of member `index`.
+    fn read_member(
+        &mut self,
+        index: usize,
+        before_body: &mut impl FnMut(&MemberHeader, &mut W) -> Result<()>,
+    ) -> Result<MemberFooter> {
+        let header = read_member_header(
+            &mut self.deflate,
+            self.magic_consumed,
+            self.options.lossy_text,
+        )?;
+        self.decoded.headers.push(header.clone());
+        check_header(
+            &header,
+            index,
+            self.options.strict,
+            self.options.unknown_os,
+            self.latest_mtime,
+            &mut self.decoded.warnings,
+        )?;
+        if let Some(profile) = &self.options.expect_header {
+            profile.check(&header)?;
+        }
+        before_body(&header, self.deflate.get_output())?;
+        loop {
+            let block = self.deflate.next_block_info()?;
+            self.stats.record(&block);
+            if block.is_final {
+                break;
+            }
+        }
+        finish_member(&mut self.deflate)
+    }
+
+    /// End the stream, which took `input_consumed` bytes.
+    fn finish(&mut self, input_consumed: u64) -> Result<()> {
+        self.done = true;
+        self.deflate.flush()?;
+        self.decoded.input_consumed = input_consumed;
+        self.stats.input_bytes = input_consumed;
+        Ok(())
+    }
+}
+
+pub(crate) fn read_member_header<R: BufRead, W: Write>(
+    deflate: &mut DeflateReader<R, TrackingWriter<W>>,
+    magic_consumed: bool,
+    lossy_text: bool,
+) -> Result<MemberHeader> {
+    let gzip_reader = GzipReader::new(deflate.get_input()).lossy_text(lossy_text);
+    let header = if magic_consumed {
+        gzip_reader.parse_header_after_magic()
+    } else {
+        gzip_reader.parse_header()
+    }?;
+    // A writer that died right after writing the header leaves this behind.
+    if GzipReader::new(deflate.get_input()).is_empty()? {
+        return Err(
+            anyhow::Error::new(DecodeError::UnexpectedEof).context("no DEFLATE data after header")
+        );
+    }
+    Ok(header)
+}
+
+/// Check the footer against the decoded data and get ready for the next member.
+/// The member's output is finished even if the check fails. Returns the footer.
+pub(crate) fn finish_member<R: BufRead, W: Write>(
+    deflate: &mut DeflateReader<R, TrackingWriter<W>>,
+) -> Result<MemberFooter> {
+    let footer = GzipReader::new(deflate.get_input()).read_footer()?;
+    let check = deflate.check_crc32_and_isize(footer.data_crc32, footer.data_size);
+    deflate.output()?;
+    check.map(|()| footer)
+}
+
+/// Report header fields RFC 1952 doesn't allow: an error in strict mode,
+/// a warning otherwise.
+fn check_header(
+    header: &MemberHeader,
+    member: usize,
+    strict: bool,
+    unknown_os: UnknownOsPolicy,
+    latest_mtime: u64,
+    warnings: &mut Vec<Warning>,
+) -> Result<()> {
+    let flags = header.reserved_flags;
+    if flags != 0 {
+        if strict {
+            bail!(DecodeError::ReservedFlags { flags });
+        }
+        warnings.push(Warning::ReservedFlags { member, flags });
+    }
+    if !header.has_known_os() {
+        let os = header.os;
+        match unknown_os {
+            UnknownOsPolicy::Ignore => {}
+            UnknownOsPolicy::Warn => warnings.push(Warning::UnknownOs { member, os }),
+            UnknownOsPolicy::Reject => bail!(DecodeError::UnknownOs { os }),
+        }
+    }
+    let mtime = header.modification_time;
+    if u64::from(mtime) > latest_mtime {
+        warnings.push(Warning::FutureMtime { member, mtime });
+    }
+    if header.lossy_text {
+        warnings.push(Warning::LossyText { member });
+    }
+    Ok(())
+}
+
+/// Position of the last bit the decoder consumed, in the whole input.
+fn decode_offset<R: BufRead, W: Write>(
+    deflate: &mut DeflateReader<TrackingReader<R>, TrackingWriter<W>>,
+) -> DecodeOffset {
+    let buffered = u64::from(deflate.buffered_bits());
+    DecodeOffset {
+        bit: deflate.get_input().byte_count() * 8 - buffered,
+    }
+}
+
+fn footer_mismatch(error: &anyhow::Error) -> Option<DecodeError> {
+    match error.downcast_ref::<DecodeError>()? {
+        error @ (DecodeError::DataCrcMismatch { .. } | DecodeError::LengthMismatch { .. }) => {
+            Some(error.clone())
+        }
+        _ => None,
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompress;
+    use crate::test_data::{FIRST, SECOND};
+    use std::io;
+
+    #[test]
+    fn one_member_at_a_time() -> Result<()> {
+        let data = [FIRST, SECOND].concat();
+        let mut first = Vec::new();
+        decompress(FIRST, &mut first)?;
+        let mut expected = Vec::new();
+        decompress(data.as_slice(), &mut expected)?;
+
+        let mut decoder = Decoder::new(data.as_slice(), Vec::new());
+        assert!(decoder.decode_one()?.is_some());
+        assert_eq!(decoder.decoded().members, 1);
+        assert_eq!(*decoder.get_output(), first);
+
+        decoder.decode_all()?;
+        assert!(decoder.decode_one()?.is_none());
+        assert_eq!(*decoder.get_output(), expected);
+        let decoded = decoder.into_decoded();
+        assert_eq!(decoded.members, 2);
+        assert_eq!(decoded.headers.len(), 2);
+        assert_eq!(decoded.input_consumed, data.len() as u64);
+        Ok(())
+    }
+
+    #[test]
+    fn lenient_footer_mismatch() -> Result<()> {
+        #[derive(Default)]
+        struct Flushes(usize);
+
+        impl Write for Flushes {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                self.0 += 1;
+                Ok(())
+            }
+        }
+
+        let mut corrupt = FIRST.to_vec();
+        let crc = corrupt.len() - 8;
+        corrupt[crc] ^= 0xff;
+        let options = DecodeOptions::builder().lenient(true).build();
+
+        let mut good = Flushes::default();
+        Decoder::with_options(options.clone(), FIRST, &mut good).decode_all()?;
+        let mut bad = Flushes::default();
+        let mut decoder = Decoder::with_options(options, corrupt.as_slice(), &mut bad);
+        assert_eq!(decoder.decode_all()?.failed_members.len(), 1);
+        // The member is finished once, by finish_member, not again on failure.
+        assert_eq!(bad.0, good.0);
+        Ok(())
+    }
+
+    #[test]
+    fn options_apply() -> Result<()> {
+        let data = [FIRST, SECOND].concat();
+        let options = DecodeOptions::builder().max_members(1).build();
+        let mut decoder = Decoder::with_options(options, data.as_slice(), Vec::new());
+        assert!(decoder.decode_one()?.is_some());
+        let error = decoder.decode_one().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::TooManyMembers { limit: 1 })
+        );
+        Ok(())
+    }
+}
@@ -1,12 +1,18 @@
 #![forbid(unsafe_code)]
 
-use std::io::{BufRead, Write};
+use std::io::{self, BufRead, Read, Write};
 
 use anyhow::{bail, Context, Result};
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt};
 
+use crate::checksum::{Checksum, NoChecksum};
+use crate::error::{DecodeError, DecodeOffset};
+use crate::gzip::FooterCheck;
 use crate::huffman_coding::HuffmanCoding;
 use crate::huffman_coding::{DistanceToken, LitLenToken};
+use crate::options::FlushPolicy;
+use crate::sink::OutputSink;
+use crate::state::DecoderState;
 use crate::tracking_writer::TrackingWriter;
 use crate::{
     bit_reader::BitReader,
@@ -21,7 +27,28 @@ pub struct BlockHeader {
     pub compression_type: CompressionType,
 }
 
-#[derive(Debug)]
+impl BlockHeader {
+    /// Read the 3-bit BFINAL/BTYPE header of a block, leaving `reader` at
+    /// the start of the block body.
+    pub fn parse<T: BufRead>(reader: &mut BitReader<T>) -> Result<BlockHeader> {
+        let is_final = reader.read_bool().context("bfinal read")?;
+        let btype = reader.read_bits(2).context("btype read")?.bits();
+
+        let cm = match btype {
+            0 => CompressionType::Uncompressed,
+            1 => CompressionType::FixedTree,
+            2 => CompressionType::DynamicTree,
+            3 => CompressionType::Reserved,
+            _ => bail!(DecodeError::Internal("BTYPE wider than two bits")),
+        };
+        Ok(BlockHeader {
+            is_final,
+            compression_type: cm,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CompressionType {
     Uncompressed = 0,
     FixedTree = 1,
@@ -29,124 +56,935 @@ pub enum CompressionType {
     Reserved = 3,
 }
 
+/// Shape of a decoded block, for analysing how a stream was compressed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockInfo {
+    pub is_final: bool,
+    pub compression_type: CompressionType,
+    /// Size of the block in the compressed stream, including its header and
+    /// the padding before stored data.
+    pub compressed_bits: u64,
+    pub decoded_bytes: u64,
+}
+
+impl BlockInfo {
+    /// Whether this is the empty, non-final stored block a sync or full
+    /// flush leaves behind (`00 00 ff ff` after the header). pigz ends each
+    /// of its 128 KiB input chunks with one, so a run of them at regular
+    /// intervals of decoded output is the usual sign of pigz. The next block
+    /// starts on a byte boundary, but only output of `pigz -i` keeps later
+    /// back-references from reaching across it.
+    pub fn is_flush_marker(&self) -> bool {
+        !self.is_final
+            && self.compression_type == CompressionType::Uncompressed
+            && self.decoded_bytes == 0
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
-pub struct DeflateReader<T, W> {
+/// Where the reader is within the current block.
+enum BlockState {
+    Header,
+    Huffman {
+        is_final: bool,
+        compression_type: CompressionType,
+        letlentoken: HuffmanCoding<LitLenToken>,
+        distancetoken: HuffmanCoding<DistanceToken>,
+    },
+}
+
+/// DEFLATE decoder reading from `T` and putting what it decodes into the
+/// sink `O`, usually a [`TrackingWriter`].
+pub struct DeflateReader<T, O> {
     bit_reader: BitReader<T>,
-    writer: TrackingWriter<W>,
+    writer: O,
+    state: BlockState,
+    output_limit: Option<u64>,
+    total_output: u64,
+    block_start_bit: u64,
+    block_start_output: u64,
+    flush_policy: FlushPolicy,
+    flushed_output: u64,
 }
 
-impl<T: BufRead, W: Write> DeflateReader<T, W> {
-    pub fn new(bit_reader: BitReader<T>, writer: TrackingWriter<W>) -> Self {
-        Self { bit_reader, writer }
+impl<T: BufRead, O: OutputSink> DeflateReader<T, O> {
+    pub fn new(bit_reader: BitReader<T>, writer: O) -> Self {
+        Self {
+            bit_reader,
+            writer,
+            state: BlockState::Header,
+            output_limit: None,
+            total_output: 0,
+            block_start_bit: 0,
+            block_start_output: 0,
+            flush_policy: FlushPolicy::default(),
+            flushed_output: 0,
+        }
     }
 
-    pub fn next_block(&mut self) -> Result<bool> {
-        let bfinal = self.bit_reader.read_bits(1).context("bfinal read")?.bits();
-        let btype = self.bit_reader.read_bits(2).context("btype read")?.bits();
+    /// Choose when the output writer is flushed. With
+    /// [`FlushPolicy::AtEnd`], [`DeflateReader::output`] doesn't flush
+    /// either; call [`DeflateReader::flush`] once done.
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.flush_policy = policy;
+    }
 
-        let cm = match btype {
-            0 => CompressionType::Uncompressed,
-            1 => CompressionType::FixedTree,
-            2 => CompressionType::DynamicTree,
-            3 => CompressionType::Reserved,
-            _ => unreachable!("reach bad btype"),
+    /// Flush the output writer.
+    pub fn flush(&mut self) -> Result<()> {
+        self.flushed_output = self.total_output;
+        OutputSink::flush(&mut self.writer)
+    }
+
+    /// Flush at the end of a block if the policy asks for it.
+    fn flush_after_block(&mut self) -> Result<()> {
+        let due = match self.flush_policy {
+            FlushPolicy::EveryBlock => true,
+            FlushPolicy::EveryNBytes(n) => self.total_output - self.flushed_output >= n as u64,
+            FlushPolicy::AtEnd => false,
         };
-        let block_header = BlockHeader {
-            is_final: bfinal != 0,
-            compression_type: cm,
+        if due {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Cap the number of bytes produced over the lifetime of the reader.
+    pub fn set_output_limit(&mut self, limit: Option<u64>) {
+        self.output_limit = limit;
+    }
+
+    /// Account for `len` bytes about to be written, failing if they would
+    /// go over the output limit.
+    fn reserve_output(&mut self, len: usize) -> Result<()> {
+        self.total_output += len as u64;
+        match self.output_limit {
+            Some(limit) if self.total_output > limit => {
+                bail!(DecodeError::OutputLimitExceeded { limit })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Decode a whole block. Returns whether it was the final one.
+    pub fn next_block(&mut self) -> Result<bool> {
+        Ok(self.next_block_info()?.is_final)
+    }
+
+    /// Decode a whole block and describe it.
+    pub fn next_block_info(&mut self) -> Result<BlockInfo> {
+        loop {
+            if let Some(block) = self.step()? {
+                return Ok(block);
+            }
+        }
+    }
+
+    /// Decode a small piece of the stream: either a block header (together
+    /// with the body of a stored block) or a single Huffman symbol.
+    /// Returns the block's description when this finished it.
+    pub fn step(&mut self) -> Result<Option<BlockInfo>> {
+        let block = match self.state {
+            BlockState::Header => {
+                self.block_start_bit = self.bit_reader.bit_position();
+                self.block_start_output = self.total_output;
+                if self.bit_reader.only_padding_left()? {
+                    bail!(DecodeError::MissingFinalBlock);
+                }
+                let block_header = BlockHeader::parse(&mut self.bit_reader)?;
+                self.read_data(block_header)?
+            }
+            BlockState::Huffman { .. } => self.decode_symbol()?,
         };
-        self.read_data(block_header)
+        if block.is_some() {
+            self.flush_after_block()?;
+        }
+        Ok(block)
     }
 
-    pub fn read_data(&mut self, block_header: BlockHeader) -> Result<bool> {
+    fn finish_block(&self, is_final: bool, compression_type: CompressionType) -> BlockInfo {
+        BlockInfo {
+            is_final,
+            compression_type,
+            compressed_bits: self.bit_reader.bit_position() - self.block_start_bit,
+            decoded_bytes: self.total_output - self.block_start_output,
+        }
+    }
+
+    /// Start decoding the body of a block. Stored blocks are copied out
+    /// entirely, Huffman blocks only get their trees decoded.
+    fn read_data(&mut self, block_header: BlockHeader) -> Result<Option<BlockInfo>> {
         match block_header.compression_type {
             CompressionType::Uncompressed => {
                 let reader = self.bit_reader.borrow_reader_from_boundary();
                 let len = reader.read_u16::<LittleEndian>().context("LEN")?;
                 let nlen = reader.read_u16::<LittleEndian>().context("NLEN")?;
+                self.bit_reader.advance(4);
                 if len != !nlen {
-                    bail!("nlen check failed")
+                    bail!(DecodeError::NlenMismatch { len, nlen })
                 }
 
-                let mut buffer: Vec<u8> = vec![0; len.into()];
-                reader
-                    .read_exact(&mut buffer)
-                    .context("uncompressed read")?;
-                self.writer
-                    .write_all(&buffer)
-                    .context("uncompressed write")?;
-                Ok(block_header.is_final)
+                self.reserve_output(len.into())?;
+                let copied = self.copy_stored(len.into())?;
+                if copied < usize::from(len) {
+                    return Err(
+                        anyhow::Error::new(DecodeError::UnexpectedEof).context(format!(
+                            "stored block declares {} bytes, input ends after {}",
+                            len, copied
+                        )),
+                    );
+                }
+                Ok(Some(self.finish_block(
+                    block_header.is_final,
+                    CompressionType::Uncompressed,
+                )))
             }
             CompressionType::FixedTree => {
                 let (letlentoken, distancetoken) =
                     decode_fixed_trees().context("fixed tree failed")?;
-                match self.decode_by_tokens(letlentoken, distancetoken) {
-                    Ok(()) => Ok(block_header.is_final),
-                    _ => {
-                        bail!("parse after fixed tree failed")
-                    }
-                }
+                self.state = BlockState::Huffman {
+                    is_final: block_header.is_final,
+                    compression_type: block_header.compression_type,
+                    letlentoken,
+                    distancetoken,
+                };
+                Ok(None)
             }
             CompressionType::DynamicTree => {
                 let (letlentoken, distancetoken) =
                     decode_dynamic_tree(&mut self.bit_reader).context("dynamic tree failed")?;
-                match self.decode_by_tokens(letlentoken, distancetoken) {
-                    Ok(()) => Ok(block_header.is_final),
-                    _ => {
-                        bail!("parse after dynamic tree failed")
-                    }
-                }
-            }
-            _ => {
-                bail!("unsupported block type")
+                self.state = BlockState::Huffman {
+                    is_final: block_header.is_final,
+                    compression_type: block_header.compression_type,
+                    letlentoken,
+                    distancetoken,
+                };
+                Ok(None)
             }
+            CompressionType::Reserved => bail!(DecodeError::ReservedBlockType),
         }
     }
 
-    fn decode_by_tokens(
-        &mut self,
-        letlentoken: HuffmanCoding<LitLenToken>,
-        distancetoken: HuffmanCoding<DistanceToken>,
-    ) -> Result<()> {
-        loop {
-            match letlentoken.read_symbol(&mut self.bit_reader)? {
-                LitLenToken::Literal(symbol) => {
-                    self.writer.write_u8(symbol)?;
-                }
-                LitLenToken::EndOfBlock => break,
-                LitLenToken::Length { base, extra_bits } => {
-                    let len = self.bit_reader.read_bits(extra_bits)?.bits() + base;
-
-                    let distancetoken = distancetoken.read_symbol(&mut self.bit_reader)?;
-                    let dist = self.bit_reader.read_bits(distancetoken.extra_bits)?.bits()
-                        + distancetoken.base;
-                    self.writer.write_previous(dist.into(), len.into())?;
-                }
+    fn decode_symbol(&mut self) -> Result<Option<BlockInfo>> {
+        let BlockState::Huffman {
+            is_final,
+            compression_type,
+            letlentoken,
+            distancetoken,
+        } = &self.state
+        else {
+            bail!(DecodeError::Internal(
+                "decode_symbol outside of a huffman block"
+            ))
+        };
+
+        match letlentoken.read_symbol(&mut self.bit_reader)? {
+            LitLenToken::Literal(symbol) => {
+                self.reserve_output(1)?;
+                self.writer.push(&[symbol])?;
+            }
+            LitLenToken::EndOfBlock => {
+                let block = self.finish_block(*is_final, *compression_type);
+                self.state = BlockState::Header;
+                return Ok(Some(block));
+            }
+            LitLenToken::Length { base, extra_bits } => {
+                let len = self.bit_reader.read_bits(extra_bits)?.bits() + base;
+
+                let distancetoken = distancetoken.read_symbol(&mut self.bit_reader)?;
+                let dist = self.bit_reader.read_bits(distancetoken.extra_bits)?.bits()
+                    + distancetoken.base;
+                self.reserve_output(len.into())?;
+                self.writer.push_back_reference(dist.into(), len.into())?;
             }
         }
-        Ok(())
+        Ok(None)
+    }
+
+    /// Hand up to `len` bytes of a stored block from the input to the
+    /// sink, see [`OutputSink::push_from`]. Returns how many there were.
+    fn copy_stored(&mut self, len: usize) -> Result<usize> {
+        let reader = self.bit_reader.borrow_reader_from_boundary();
+        let copied = self.writer.push_from(reader, len)?;
+        self.bit_reader.advance(copied);
+        Ok(copied)
+    }
+
+    /// See [`BitReader::bit_position`].
+    pub fn bit_position(&self) -> u64 {
+        self.bit_reader.bit_position()
+    }
+
+    /// See [`BitReader::buffered_bits`].
+    pub fn buffered_bits(&self) -> u8 {
+        self.bit_reader.buffered_bits()
     }
 
     pub fn get_input(&mut self) -> &mut T {
         self.bit_reader.borrow_reader_from_boundary()
     }
 
+    /// The sink decoded data goes to.
+    pub fn get_sink(&mut self) -> &mut O {
+        &mut self.writer
+    }
+}
+
+impl<T: BufRead, W: Write, C: Checksum> DeflateReader<T, TrackingWriter<W, C>> {
+    /// See [`TrackingWriter::set_dictionary`].
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        self.writer.set_dictionary(dictionary);
+    }
+
+    /// Snapshot the reader. Fails in the middle of a Huffman block.
+    pub fn save_state(&self) -> Result<DecoderState> {
+        if !matches!(self.state, BlockState::Header) {
+            bail!("decoder state can only be saved between blocks");
+        }
+        Ok(DecoderState {
+            history: self.writer.history().collect(),
+            checksum: self.writer.checksum(),
+            byte_count: self.writer.total_byte_count(),
+            total_output: self.total_output,
+            bit_position: self.bit_reader.bit_position(),
+            buffered_bits: self.bit_reader.buffered(),
+        })
+    }
+
+    /// Continue from `state`, dropping anything this reader decoded so far.
+    /// The input must start at [`DecoderState::input_offset`] of the input
+    /// the state was saved from.
+    pub fn restore_state(&mut self, state: &DecoderState) {
+        self.state = BlockState::Header;
+        self.total_output = state.total_output;
+        self.bit_reader
+            .restore(state.buffered_bits, state.bit_position);
+        self.writer
+            .restore(&state.history, state.byte_count, state.checksum);
+    }
+
+    pub fn get_output(&mut self) -> &mut W {
+        self.writer.get_mut()
+    }
+
+    /// Finish a member or stream: flush the output writer unless the policy
+    /// is [`FlushPolicy::AtEnd`], and start over with an empty history and
+    /// checksum.
     pub fn output(&mut self) -> Result<()> {
-        self.writer.flush()?;
+        self.state = BlockState::Header;
+        if self.flush_policy != FlushPolicy::AtEnd {
+            self.flush()?;
+        }
         self.writer.clear()?;
         Ok(())
     }
 
+    pub fn check_adler32(&mut self, adler32: u32) -> Result<()> {
+        let actual = self.writer.checksum();
+        if adler32 != actual {
+            bail!(DecodeError::Adler32Mismatch {
+                expected: adler32,
+                actual,
+            })
+        }
+        Ok(())
+    }
+}
+
+impl<T: BufRead, W: Write> DeflateReader<T, TrackingWriter<W>> {
+    /// Compare a footer's CRC32 and ISIZE to the data decoded so far,
+    /// without failing, e.g. to log a mismatch and carry on.
+    pub fn compare_footer(&mut self, crc32: u32, isize: u32) -> FooterCheck {
+        let computed_crc = self.writer.crc32();
+        let computed_size = self.writer.byte_count();
+        FooterCheck {
+            crc_ok: crc32 == computed_crc,
+            size_ok: isize == computed_size,
+            computed_crc,
+            computed_size,
+        }
+    }
+
+    /// Like [`DeflateReader::compare_footer`], but a mismatch is an error.
     pub fn check_crc32_and_isize(&mut self, crc32: u32, isize: u32) -> Result<()> {
-        if crc32 != self.writer.crc32() {
-            bail!("crc32 check failed")
+        let check = self.compare_footer(crc32, isize);
+        if !check.crc_ok {
+            bail!(DecodeError::DataCrcMismatch {
+                expected: crc32,
+                actual: check.computed_crc,
+            })
         }
-        if isize != self.writer.byte_count() {
-            bail!("length check failed")
+        if !check.size_ok {
+            bail!(DecodeError::LengthMismatch {
+                expected: isize,
+                actual: check.computed_size,
+            })
         }
         Ok(())
     }
 }
 
-// TODO: your code goes here.
+////////////////////////////////////////////////////////////////////////////////
+
+/// Decode a bare DEFLATE stream (no gzip or zlib framing) up to its final block.
+///
+/// Input is read no further than the byte the final block ends in, so a
+/// container like ZIP can go on reading its next entry from `input`.
+/// Returns how many high bits of that last byte were padding.
+pub fn inflate<R: BufRead, W: Write>(input: R, output: W) -> Result<u8> {
+    inflate_to_sink(input, TrackingWriter::with_checksum(output, NoChecksum))
+}
+
+/// Like [`inflate`], but into an [`OutputSink`] instead of an `io::Write`.
+/// Pass `&mut sink` to look at the sink afterwards.
+pub fn inflate_to_sink<R: BufRead, O: OutputSink>(input: R, sink: O) -> Result<u8> {
+    inflate_limited(input, sink, None)
+}
+
+/// [`inflate_to_sink`] failing once more than `output_limit` bytes would be
+/// produced.
+pub(crate) fn inflate_limited<R: BufRead, O: OutputSink>(
+    input: R,
+    sink: O,
+    output_limit: Option<u64>,
+) -> Result<u8> {
+    let mut deflate = DeflateReader::new(BitReader::new(input), sink);
+    deflate.set_output_limit(output_limit);
+    loop {
+        match deflate.next_block() {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(error) => {
+                let bit = deflate.bit_position();
+                return Err(error.context(DecodeOffset { bit }));
+            }
+        }
+    }
+    let padding = deflate.buffered_bits();
+    deflate.flush()?;
+    Ok(padding)
+}
+
+/// Walk the blocks of a bare DEFLATE stream, decoding them into a sink.
+pub fn inflate_blocks<R: BufRead>(input: R) -> InflateBlocks<R> {
+    InflateBlocks {
+        deflate: Some(DeflateReader::new(
+            BitReader::new(input),
+            TrackingWriter::with_checksum(io::sink(), NoChecksum),
+        )),
+    }
+}
+
+/// Iterator returned by [`inflate_blocks`]. Ends after the final block or
+/// the first error.
+pub struct InflateBlocks<R> {
+    deflate: Option<DeflateReader<R, TrackingWriter<io::Sink, NoChecksum>>>,
+}
+
+impl<R: BufRead> Iterator for InflateBlocks<R> {
+    type Item = Result<BlockInfo>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let block = self.deflate.as_mut()?.next_block_info();
+        if !matches!(
+            block,
+            Ok(BlockInfo {
+                is_final: false,
+                ..
+            })
+        ) {
+            self.deflate = None;
+        }
+        Some(block)
+    }
+}
+
+/// Read the decompressed contents of a bare DEFLATE stream through
+/// [`Read`].
+pub fn inflate_reader<R: BufRead>(input: R) -> InflateReader<R> {
+    InflateReader {
+        deflate: DeflateReader::new(
+            BitReader::new(input),
+            TrackingWriter::with_checksum(Vec::new(), NoChecksum),
+        ),
+        position: 0,
+        done: false,
+        failure: None,
+    }
+}
+
+/// Reader returned by [`inflate_reader`].
+///
+/// Each `read` decodes only as much as it needs to fill `buf`. Decoded bytes
+/// that don't fit are kept for the next call, and the 32 KiB history lives on
+/// in the [`TrackingWriter`], so back-references can reach into output that
+/// earlier calls already returned. Decoding errors are reported as
+/// [`io::ErrorKind::InvalidData`], unless they came from reading `input`.
+/// After an error every later `read` fails the same way, except after
+/// [`io::ErrorKind::Interrupted`], which can be retried.
+pub struct InflateReader<R> {
+    deflate: DeflateReader<R, TrackingWriter<Vec<u8>, NoChecksum>>,
+    position: usize,
+    done: bool,
+    /// Kind and message of the error that stopped decoding.
+    failure: Option<(io::ErrorKind, String)>,
+}
+
+impl<R: BufRead> InflateReader<R> {
+    fn fill(&mut self, wanted: usize) -> Result<()> {
+        while !self.done && self.deflate.get_output().len() - self.position < wanted {
+            if matches!(self.deflate.step()?, Some(block) if block.is_final) {
+                self.done = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for InflateReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.deflate.get_output().len() {
+            self.deflate.get_output().clear();
+            self.position = 0;
+        }
+        if let Some((kind, message)) = &self.failure {
+            return Err(io::Error::new(*kind, message.clone()));
+        }
+        if let Err(error) = self.fill(buf.len()) {
+            let error = match error.downcast::<io::Error>() {
+                Ok(error) => error,
+                Err(error) => io::Error::new(io::ErrorKind::InvalidData, error),
+            };
+            if error.kind() != io::ErrorKind::Interrupted {
+                self.failure = Some((error.kind(), error.to_string()));
+            }
+            return Err(error);
+        }
+
+        let pending = &self.deflate.get_output()[self.position..];
+        let n = pending.len().min(buf.len());
+        buf[..n].copy_from_slice(&pending[..n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_reader::BitSequence;
+    use crate::bit_writer::BitWriter;
+    use crate::decompress;
+    use crate::gzip::GzipReader;
+    use crate::test_data::{FIRST, RAW, STORED};
+    use std::io::BufReader;
+
+    #[test]
+    fn inflate_raw() -> Result<()> {
+        let mut expected = Vec::new();
+        decompress(FIRST, &mut expected)?;
+
+        let mut output = Vec::new();
+        inflate(RAW, &mut output)?;
+        assert_eq!(output, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn inflate_fixed() -> Result<()> {
+        let data = [75, 76, 74, 78, 132, 33, 133, 180, 204, 138, 212, 20, 46, 0];
+        let mut output = Vec::new();
+        inflate(data.as_slice(), &mut output)?;
+        assert_eq!(output, b"abcabcabcabc fixed\n");
+        Ok(())
+    }
+
+    #[test]
+    fn stored_block() -> Result<()> {
+        let expected = b"stored block payload\n".repeat(3);
+        let mut output = Vec::new();
+        decompress(STORED, &mut output)?;
+        assert_eq!(output, expected);
+
+        let mut output = Vec::new();
+        decompress(BufReader::with_capacity(5, STORED), &mut output)?;
+        assert_eq!(output, expected);
+
+        let error = decompress(&STORED[..40], Vec::new()).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::UnexpectedEof)
+        );
+        assert_eq!(
+            format!("{:#}", error),
+            "gzip member 0: at bit 320 (byte 40): stored block declares 63 bytes, \
+             input ends after 25: unexpected end of input"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nlen_mismatch() -> Result<()> {
+        // BFINAL = 1, BTYPE = 00, then LEN = 3 and an NLEN that is off by one.
+        let data = [0b001, 3, 0, 0xfb, 0xff, b'a', b'b', b'c'];
+        let error = inflate(data.as_slice(), Vec::new()).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::NlenMismatch {
+                len: 3,
+                nlen: 0xfffb
+            })
+        );
+        assert_eq!(
+            error.downcast_ref::<DecodeOffset>(),
+            Some(&DecodeOffset { bit: 40 })
+        );
+
+        let data = [0b001, 3, 0, 0xfc, 0xff, b'a', b'b', b'c'];
+        let mut output = Vec::new();
+        inflate(data.as_slice(), &mut output)?;
+        assert_eq!(output, b"abc");
+        Ok(())
+    }
+
+    #[test]
+    fn inflate_stops_at_byte_boundary() -> Result<()> {
+        let data = [RAW, b"next entry"].concat();
+        let mut input = data.as_slice();
+        let padding = inflate(&mut input, io::sink())?;
+        assert_eq!(input, b"next entry");
+        let bits = inflate_blocks(RAW)
+            .map(|block| Ok(block?.compressed_bits))
+            .sum::<Result<u64>>()?;
+        assert_eq!(u64::from(padding), RAW.len() as u64 * 8 - bits);
+
+        // A stored final block ends on a byte boundary.
+        let mut input = &[0b001, 0, 0, 0xff, 0xff, 0x42][..];
+        assert_eq!(inflate(&mut input, io::sink())?, 0);
+        assert_eq!(input, [0x42]);
+        Ok(())
+    }
+
+    #[test]
+    fn counting_sink() -> Result<()> {
+        #[derive(Default)]
+        struct Counter {
+            bytes: usize,
+            matches: usize,
+            flushes: usize,
+        }
+
+        impl OutputSink for Counter {
+            fn push(&mut self, bytes: &[u8]) -> Result<()> {
+                self.bytes += bytes.len();
+                Ok(())
+            }
+
+            fn push_back_reference(&mut self, _dist: usize, len: usize) -> Result<()> {
+                self.bytes += len;
+                self.matches += 1;
+                Ok(())
+            }
+
+            fn flush(&mut self) -> Result<()> {
+                self.flushes += 1;
+                Ok(())
+            }
+        }
+
+        let mut expected = Vec::new();
+        inflate(RAW, &mut expected)?;
+
+        let mut counter = Counter::default();
+        inflate_to_sink(RAW, &mut counter)?;
+        assert_eq!(counter.bytes, expected.len());
+        assert!(counter.matches > 0);
+        assert!(counter.flushes > 0);
+
+        // A final stored block holding "abc".
+        let mut counter = Counter::default();
+        inflate_to_sink(
+            &[0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c'][..],
+            &mut counter,
+        )?;
+        assert_eq!((counter.bytes, counter.matches), (3, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn inflate_reader_small_reads() -> Result<()> {
+        let mut expected = Vec::new();
+        inflate(RAW, &mut expected)?;
+
+        let mut output = Vec::new();
+        inflate_reader(RAW).read_to_end(&mut output)?;
+        assert_eq!(output, expected);
+
+        // Matches reach back into bytes handed out by earlier reads.
+        let mut reader = inflate_reader(RAW);
+        let mut output = Vec::new();
+        let mut buf = [0; 7];
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            output.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(output, expected);
+        assert_eq!(reader.read(&mut buf)?, 0);
+
+        let mut output = Vec::new();
+        let error = inflate_reader(&RAW[..RAW.len() / 2])
+            .read_to_end(&mut output)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        Ok(())
+    }
+
+    #[test]
+    fn inflate_reader_stays_failed() {
+        // A stored block with a bad NLEN, then one with truncated input.
+        let bad_nlen = [0b001, 2, 0, 0, 0, b'a', b'b'];
+        for (data, kind) in [
+            (&bad_nlen[..], io::ErrorKind::InvalidData),
+            (&RAW[..RAW.len() / 2], io::ErrorKind::UnexpectedEof),
+        ] {
+            let mut reader = inflate_reader(data);
+            let mut output = Vec::new();
+            assert_eq!(reader.read_to_end(&mut output).unwrap_err().kind(), kind);
+            let mut buf = [0; 16];
+            for _ in 0..2 {
+                assert_eq!(reader.read(&mut buf).unwrap_err().kind(), kind);
+            }
+            assert_eq!(reader.read_to_end(&mut output).unwrap_err().kind(), kind);
+        }
+    }
+
+    #[test]
+    fn block_info() -> Result<()> {
+        let blocks = inflate_blocks(RAW).collect::<Result<Vec<_>>>()?;
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].compression_type, CompressionType::DynamicTree);
+        assert_eq!(blocks[0].compressed_bits.div_ceil(8), RAW.len() as u64);
+        assert_eq!(blocks[0].decoded_bytes, 550);
+
+        // A non-final stored block holding "ab" followed by a final, empty one.
+        let data = [0b000, 2, 0, 0xfd, 0xff, b'a', b'b', 0b001, 0, 0, 0xff, 0xff];
+        let blocks = inflate_blocks(data.as_slice()).collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            blocks,
+            vec![
+                BlockInfo {
+                    is_final: false,
+                    compression_type: CompressionType::Uncompressed,
+                    compressed_bits: 56,
+                    decoded_bytes: 2,
+                },
+                BlockInfo {
+                    is_final: true,
+                    compression_type: CompressionType::Uncompressed,
+                    compressed_bits: 40,
+                    decoded_bytes: 0,
+                },
+            ]
+        );
+        assert!(!blocks[0].is_flush_marker());
+        assert!(!blocks[1].is_flush_marker());
+
+        // A sync flush between two stored blocks.
+        let data = [
+            0b000, 1, 0, 0xfe, 0xff, b'a', 0b000, 0, 0, 0xff, 0xff, 0b001, 1, 0, 0xfe, 0xff, b'b',
+        ];
+        let blocks = inflate_blocks(data.as_slice()).collect::<Result<Vec<_>>>()?;
+        let markers: Vec<_> = blocks.iter().map(BlockInfo::is_flush_marker).collect();
+        assert_eq!(markers, [false, true, false]);
+        Ok(())
+    }
+
+    #[test]
+    fn reserved_block_type() {
+        // BFINAL = 1, BTYPE = 11, followed by bytes that must be left alone.
+        let data = [0b111, 0xaa, 0xbb];
+        let mut input = data.as_slice();
+        let error = inflate(&mut input, Vec::new()).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::ReservedBlockType)
+        );
+        assert_eq!(input, [0xaa, 0xbb]);
+    }
+
+    #[test]
+    fn compare_footer() -> Result<()> {
+        let mut deflate = DeflateReader::new(BitReader::new(RAW), TrackingWriter::new(Vec::new()));
+        while !deflate.next_block()? {}
+        let footer = GzipReader::new(&FIRST[FIRST.len() - 8..]).read_footer()?;
+
+        let check = deflate.compare_footer(footer.data_crc32, footer.data_size);
+        assert!(check.is_ok());
+        assert_eq!(check.computed_crc, footer.data_crc32);
+        assert_eq!(check.computed_size, footer.data_size);
+
+        let check = deflate.compare_footer(!footer.data_crc32, footer.data_size);
+        assert_eq!((check.crc_ok, check.size_ok), (false, true));
+        assert_eq!(check.computed_crc, footer.data_crc32);
+        let check = deflate.compare_footer(footer.data_crc32, 1);
+        assert_eq!((check.crc_ok, check.size_ok), (true, false));
+        assert!(deflate.check_crc32_and_isize(footer.data_crc32, 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn reserved_litlen_symbol() -> Result<()> {
+        // A fixed block using literal/length symbol 286, coded as 0xc6.
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(BitSequence::new(0b011, 3))?;
+        writer.write_bits(BitSequence::from_msb(0xc6, 8))?;
+        let data = writer.borrow_writer_from_boundary()?.clone();
+
+        let error = inflate(data.as_slice(), Vec::new()).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::InvalidSymbol {
+                alphabet: "literal/length",
+                value: 286,
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reserved_distance_symbol() -> Result<()> {
+        // A fixed block with 'a' and then a match using distance symbol 30.
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(BitSequence::new(0b011, 3))?;
+        writer.write_bits(BitSequence::from_msb(0x30 + u16::from(b'a'), 8))?;
+        writer.write_bits(BitSequence::from_msb(1, 7))?;
+        writer.write_bits(BitSequence::from_msb(30, 5))?;
+        let data = writer.borrow_writer_from_boundary()?.clone();
+
+        let error = inflate(data.as_slice(), Vec::new()).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::InvalidDistanceSymbol { symbol: 30 })
+        );
+        Ok(())
+    }
+
+    /// A dynamic block with lit/len codes 'a' = 0, 256 = 10, 257 = 11 and
+    /// a distance tree holding only symbol 0 with length `distance_len`,
+    /// followed by `codes` given most significant bit first.
+    fn dynamic_block(distance_len: u8, codes: &[(u16, u8)]) -> Result<Vec<u8>> {
+        let mut writer = BitWriter::new(Vec::new());
+        let mut write = |bits, len| writer.write_bits(BitSequence::new(bits, len));
+        write(0b101, 3)?; // BFINAL, BTYPE = 10
+        write(1, 5)?; // HLIT = 258
+        write(0, 5)?; // HDIST = 1
+        write(14, 4)?; // HCLEN = 18
+
+        // Code length code lengths in the RFC's order: 18 -> 1, 0 -> 3, 2 -> 2, 1 -> 3.
+        for len in [0, 0, 1, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 3] {
+            write(len, 3)?;
+        }
+        let zeros = BitSequence::from_msb(0b0, 1);
+        // Codes of the code length symbols 0, 1 and 2.
+        let length = [
+            BitSequence::from_msb(0b110, 3),
+            BitSequence::from_msb(0b111, 3),
+            BitSequence::from_msb(0b10, 2),
+        ];
+        let (one, two) = (length[1], length[2]);
+        for (code, extra) in [
+            (zeros, BitSequence::new(97 - 11, 7)),
+            (one, BitSequence::new(0, 0)),
+            (zeros, BitSequence::new(138 - 11, 7)),
+            (zeros, BitSequence::new(20 - 11, 7)),
+            (two, BitSequence::new(0, 0)),
+            (two, BitSequence::new(0, 0)),
+            (length[usize::from(distance_len)], BitSequence::new(0, 0)),
+        ] {
+            writer.write_bits(code)?;
+            writer.write_bits(extra)?;
+        }
+        for &(bits, len) in codes {
+            writer.write_bits(BitSequence::from_msb(bits, len))?;
+        }
+        Ok(writer.borrow_writer_from_boundary()?.clone())
+    }
+
+    #[test]
+    fn single_distance_code() -> Result<()> {
+        // 'a', then length 3 at distance 1, then end of block.
+        let data = dynamic_block(1, &[(0b0, 1), (0b11, 2), (0b0, 1), (0b10, 2)])?;
+        let mut output = Vec::new();
+        inflate(data.as_slice(), &mut output)?;
+        assert_eq!(output, b"aaaa");
+
+        // The same match with distance code 1, which has no symbol.
+        let data = dynamic_block(1, &[(0b0, 1), (0b11, 2), (0b1, 1), (0b10, 2)])?;
+        let error = inflate(data.as_slice(), io::sink()).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::UnassignedCode {
+                alphabet: "distance"
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn no_distance_codes() -> Result<()> {
+        // HDIST = 1 with length 0: literals only. 'a', 'a', end of block.
+        let data = dynamic_block(0, &[(0b0, 1), (0b0, 1), (0b10, 2)])?;
+        let mut output = Vec::new();
+        inflate(data.as_slice(), &mut output)?;
+        assert_eq!(output, b"aa");
+
+        // A match needs a distance code, and there are none.
+        let data = dynamic_block(0, &[(0b0, 1), (0b11, 2), (0b0, 1), (0b10, 2)])?;
+        let error = inflate(data.as_slice(), io::sink()).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::UnassignedCode {
+                alphabet: "distance"
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_block_header() -> Result<()> {
+        let mut reader = BitReader::new(RAW);
+        let header = BlockHeader::parse(&mut reader)?;
+        assert!(header.is_final);
+        assert_eq!(header.compression_type, CompressionType::DynamicTree);
+        assert_eq!(reader.bit_position(), 3);
+
+        let mut reader = BitReader::new(STORED);
+        reader.borrow_reader_from_boundary().consume(10);
+        let header = BlockHeader::parse(&mut reader)?;
+        assert_eq!(header.compression_type, CompressionType::Uncompressed);
+        Ok(())
+    }
+
+    #[test]
+    fn missing_final_block() -> Result<()> {
+        // An empty fixed block without BFINAL, and nothing after it.
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(BitSequence::new(0b010, 3))?;
+        writer.write_bits(BitSequence::new(0, 7))?;
+        let data = writer.borrow_writer_from_boundary()?.clone();
+
+        for data in [&data[..], &[]] {
+            let error = inflate(data, Vec::new()).unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<DecodeError>(),
+                Some(&DecodeError::MissingFinalBlock)
+            );
+        }
+        Ok(())
+    }
+}
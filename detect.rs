@@ -0,0 +1,137 @@
+#![forbid(unsafe_code)]
+
+use std::io::{BufRead, Read, Write};
+
+use anyhow::Result;
+
+use crate::checksum::NoChecksum;
+use crate::deflate::inflate_limited;
+use crate::gzip::{ID1, ID2};
+use crate::options::DecodeOptions;
+use crate::tracking_writer::TrackingWriter;
+use crate::zlib::{decompress_zlib_impl, is_zlib_header};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Framing of a compressed stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Gzip,
+    Zlib,
+    /// Bare DEFLATE, assumed when neither magic matches.
+    Deflate,
+}
+
+impl Format {
+    /// Guess the format from the first two bytes of a stream.
+    pub fn detect(prefix: &[u8]) -> Self {
+        match prefix {
+            [ID1, ID2, ..] => Self::Gzip,
+            &[cmf, flg, ..] if is_zlib_header(cmf, flg) => Self::Zlib,
+            _ => Self::Deflate,
+        }
+    }
+}
+
+/// Decode gzip, zlib or raw DEFLATE, whichever `input` looks like.
+pub fn decompress_auto<R: BufRead, W: Write>(input: R, output: W) -> Result<Format> {
+    decompress_auto_with(DecodeOptions::default(), input, output)
+}
+
+/// Like [`decompress_auto`]. Gzip input is decoded with all of `options`;
+/// zlib and raw DEFLATE only use [`max_output`].
+///
+/// [`max_output`]: crate::DecodeOptionsBuilder::max_output
+pub fn decompress_auto_with<R: BufRead, W: Write>(
+    options: DecodeOptions,
+    mut input: R,
+    output: W,
+) -> Result<Format> {
+    let buffer = input.fill_buf()?;
+    if buffer.len() >= 2 {
+        let format = Format::detect(buffer);
+        decompress_as(options, format, input, output)?;
+        return Ok(format);
+    }
+    // Only a short read left the prefix split across buffers: read it and
+    // put it back in front of the rest.
+    let mut prefix = Vec::with_capacity(2);
+    input.by_ref().take(2).read_to_end(&mut prefix)?;
+    let format = Format::detect(&prefix);
+    decompress_as(options, format, prefix.as_slice().chain(input), output)?;
+    Ok(format)
+}
+
+fn decompress_as<R: BufRead, W: Write>(
+    options: DecodeOptions,
+    format: Format,
+    input: R,
+    output: W,
+) -> Result<()> {
+    let limit = options.max_output;
+    match format {
+        Format::Gzip => {
+            crate::decompress_with(options, input, output)?;
+        }
+        Format::Zlib => decompress_zlib_impl(input, output, None, limit)?,
+        Format::Deflate => {
+            let output = TrackingWriter::with_checksum(output, NoChecksum);
+            inflate_limited(input, output, limit)?;
+        }
+    }
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::{FIRST, RAW, ZLIB};
+    use crate::DecodeError;
+    use std::io;
+
+    #[test]
+    fn detect() {
+        assert_eq!(Format::detect(&[0x1f, 0x8b, 8]), Format::Gzip);
+        assert_eq!(Format::detect(&[0x78, 0x9c]), Format::Zlib);
+        assert_eq!(Format::detect(&[0x78, 0x9d]), Format::Deflate);
+        assert_eq!(Format::detect(&[0x1f]), Format::Deflate);
+        assert_eq!(Format::detect(&[]), Format::Deflate);
+    }
+
+    #[test]
+    fn decompress_auto() -> Result<()> {
+        let mut expected = Vec::new();
+        crate::decompress(FIRST, &mut expected)?;
+
+        for (data, format) in [
+            (FIRST, Format::Gzip),
+            (ZLIB, Format::Zlib),
+            (RAW, Format::Deflate),
+        ] {
+            let mut output = Vec::new();
+            assert_eq!(super::decompress_auto(data, &mut output)?, format);
+            assert_eq!(output, expected);
+
+            // The prefix arrives one byte at a time.
+            let mut output = Vec::new();
+            let input = io::BufReader::with_capacity(1, data);
+            assert_eq!(super::decompress_auto(input, &mut output)?, format);
+            assert_eq!(output, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn output_limit() {
+        for data in [FIRST, ZLIB, RAW] {
+            let options = DecodeOptions::builder().max_output(10).build();
+            let error = decompress_auto_with(options, data, io::sink()).unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<DecodeError>(),
+                Some(&DecodeError::OutputLimitExceeded { limit: 10 })
+            );
+        }
+    }
+}
@@ -0,0 +1,197 @@
+#![forbid(unsafe_code)]
+
+use std::fmt;
+
+use crate::zlib::is_zlib_header;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Typed decode failures. They travel inside `anyhow::Error`, so callers can
+/// recover them with `error.downcast_ref::<DecodeError>()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The input doesn't start with the gzip magic `1f 8b`.
+    NotGzip { magic: [u8; 2] },
+    /// Non-gzip data follows the last member (strict mode only).
+    TrailingGarbage { offset: u64 },
+    /// The header's FHCRC doesn't match the header bytes read.
+    HeaderCrcMismatch { expected: u16, actual: u16 },
+    /// The member footer's CRC32 doesn't match the decoded data.
+    DataCrcMismatch { expected: u32, actual: u32 },
+    /// The zlib trailer's Adler-32 doesn't match the decoded data.
+    Adler32Mismatch { expected: u32, actual: u32 },
+    /// The member footer's ISIZE doesn't match the decoded length (mod 2^32).
+    LengthMismatch { expected: u32, actual: u32 },
+    /// A block header uses BTYPE = 11, which RFC 1951 reserves.
+    ReservedBlockType,
+    /// A stored block's NLEN isn't the one's complement of its LEN.
+    NlenMismatch { len: u16, nlen: u16 },
+    /// A back-reference points before the first byte of the member
+    /// (or past the 32 KiB window). `available` is 0 when the member has
+    /// produced no output yet, e.g. a reference into the previous member.
+    DistanceTooFar { distance: usize, available: usize },
+    /// A dynamic block describes an impossible Huffman code.
+    InvalidHuffmanCode(&'static str),
+    /// The code lengths a dynamic block gives the code length alphabet
+    /// don't form a complete prefix code.
+    InvalidCodeLengthTable,
+    /// The input ends between blocks without a block marked BFINAL.
+    MissingFinalBlock,
+    /// A block uses distance symbol 30 or 31, which RFC 1951 reserves.
+    InvalidDistanceSymbol { symbol: u16 },
+    /// A block uses a symbol its alphabet has no meaning for, e.g.
+    /// literal/length 286 or 287. Distance symbols 30 and 31 are reported as
+    /// [`DecodeError::InvalidDistanceSymbol`].
+    InvalidSymbol { alphabet: &'static str, value: u16 },
+    /// A dynamic block's data uses a code its Huffman table left unassigned,
+    /// e.g. the code of a distance symbol given length 0. `alphabet` is
+    /// "literal/length", "distance" or "code length".
+    UnassignedCode { alphabet: &'static str },
+    /// A member uses a compression method other than DEFLATE.
+    UnsupportedMethod { method: u8 },
+    /// A member uses something this decoder can't handle, e.g. the
+    /// encryption flag of gzip 0.x.
+    UnsupportedFeature(&'static str),
+    /// A member header sets FLG bits that RFC 1952 reserves (strict mode only).
+    ReservedFlags { flags: u8 },
+    /// A member header has an OS value RFC 1952 doesn't define (strict mode only).
+    UnknownOs { os: u8 },
+    /// A member header field differs from the expected
+    /// [`HeaderProfile`](crate::HeaderProfile). `field` is "OS", "MTIME" or "XFL".
+    HeaderMismatch {
+        field: &'static str,
+        expected: u32,
+        actual: u32,
+    },
+    /// The input ended in the middle of a structure, e.g. a header field.
+    UnexpectedEof,
+    /// Decoding would produce more than the configured `max_output`.
+    OutputLimitExceeded { limit: u64 },
+    /// The stream has more members than the configured `max_members`.
+    TooManyMembers { limit: usize },
+    /// The decoder reached a state it should never get into. This is a bug
+    /// in the decoder, not a problem with the input.
+    Internal(&'static str),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotGzip { magic } => {
+                write!(
+                    f,
+                    "expected gzip magic 1f 8b, got {:02x} {:02x}",
+                    magic[0], magic[1]
+                )?;
+                if is_zlib_header(magic[0], magic[1]) {
+                    write!(f, " (looks like zlib, try decompress_auto)")?;
+                } else if magic
+                    .iter()
+                    .all(|byte| byte.is_ascii_graphic() || byte.is_ascii_whitespace())
+                {
+                    write!(f, " (looks like uncompressed text)")?;
+                }
+                Ok(())
+            }
+            Self::TrailingGarbage { offset } => {
+                write!(f, "trailing garbage after last member at byte {}", offset)
+            }
+            Self::HeaderCrcMismatch { expected, actual } => write!(
+                f,
+                "header crc16 check failed: expected {:#06x}, got {:#06x}",
+                expected, actual
+            ),
+            Self::DataCrcMismatch { expected, actual } => write!(
+                f,
+                "crc32 check failed: expected {:#010x}, got {:#010x}",
+                expected, actual
+            ),
+            Self::Adler32Mismatch { expected, actual } => write!(
+                f,
+                "adler32 check failed: expected {:#010x}, got {:#010x}",
+                expected, actual
+            ),
+            Self::LengthMismatch { expected, actual } => write!(
+                f,
+                "length check failed: expected {} bytes, got {}",
+                expected, actual
+            ),
+            Self::ReservedBlockType => write!(f, "reserved block type"),
+            Self::NlenMismatch { len, nlen } => write!(
+                f,
+                "stored block nlen {:#06x} is not the complement of len {:#06x}",
+                nlen, len
+            ),
+            Self::DistanceTooFar {
+                distance,
+                available: 0,
+            } => write!(
+                f,
+                "back-reference distance {} before any output of the member",
+                distance
+            ),
+            Self::DistanceTooFar {
+                distance,
+                available,
+            } => write!(
+                f,
+                "back-reference distance {} exceeds the {} bytes available",
+                distance, available
+            ),
+            Self::InvalidHuffmanCode(reason) => write!(f, "invalid huffman code: {}", reason),
+            Self::InvalidCodeLengthTable => {
+                write!(f, "incomplete or over-subscribed code length code")
+            }
+            Self::MissingFinalBlock => write!(f, "input ends before the final block"),
+            Self::InvalidDistanceSymbol { symbol } => {
+                write!(f, "invalid distance symbol {}", symbol)
+            }
+            Self::InvalidSymbol { alphabet, value } => {
+                write!(f, "invalid {} symbol {}", alphabet, value)
+            }
+            Self::UnassignedCode { alphabet } => {
+                write!(f, "bits match no code of the {} alphabet", alphabet)
+            }
+            Self::UnsupportedMethod { method } => {
+                write!(f, "unsupported compression method {}", method)
+            }
+            Self::UnsupportedFeature(feature) => write!(f, "unsupported feature: {}", feature),
+            Self::ReservedFlags { flags } => {
+                write!(f, "reserved header flag bits set: {:#04x}", flags)
+            }
+            Self::UnknownOs { os } => write!(f, "unknown os value {}", os),
+            Self::HeaderMismatch {
+                field,
+                expected,
+                actual,
+            } => write!(f, "header {} is {}, expected {}", field, actual, expected),
+            Self::UnexpectedEof => write!(f, "unexpected end of input"),
+            Self::OutputLimitExceeded { limit } => {
+                write!(f, "output exceeds the limit of {} bytes", limit)
+            }
+            Self::TooManyMembers { limit } => {
+                write!(f, "stream has more than {} members", limit)
+            }
+            Self::Internal(what) => write!(f, "internal decoder error: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Where in the compressed input a decode error happened, attached as
+/// context. Recover it with `error.downcast_ref::<DecodeOffset>()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeOffset {
+    /// Bits consumed when the error was detected, counted from the start of
+    /// the input.
+    pub bit: u64,
+}
+
+impl fmt::Display for DecodeOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at bit {} (byte {})", self.bit, self.bit / 8)
+    }
+}
@@ -0,0 +1,73 @@
+#![forbid(unsafe_code)]
+
+use std::io;
+
+use anyhow::Result;
+
+use crate::detect::decompress_auto_with;
+use crate::options::DecodeOptions;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Output cap for fuzzing, so small inputs can't expand without bound.
+const FUZZ_OUTPUT_LIMIT: u64 = 1 << 24;
+
+/// Entry point for `cargo fuzz`: decode arbitrary bytes and discard the output.
+///
+/// Runs the format sniffing path and the lenient gzip path, which between them
+/// cover every decoder. Malformed input must come back as `Err`, never panic.
+pub fn fuzz_decompress(data: &[u8]) -> Result<()> {
+    let options = DecodeOptions::builder()
+        .max_output(FUZZ_OUTPUT_LIMIT)
+        .build();
+    let auto = decompress_auto_with(options, data, io::sink());
+    let options = DecodeOptions::builder()
+        .lenient(true)
+        .max_output(FUZZ_OUTPUT_LIMIT)
+        .build();
+    crate::decompress_with(options, data, io::sink())?;
+    auto.map(|_| ())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::{FIRST, RAW, ZLIB};
+
+    #[test]
+    fn valid_input() -> Result<()> {
+        fuzz_decompress(FIRST)?;
+        fuzz_decompress(&[
+            0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 3, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ])
+    }
+
+    #[test]
+    fn no_panics() {
+        for data in [FIRST, ZLIB, RAW] {
+            for len in 0..data.len() {
+                let _ = fuzz_decompress(&data[..len]);
+            }
+            for bit in 0..data.len() * 8 {
+                let mut corrupt = data.to_vec();
+                corrupt[bit / 8] ^= 1 << (bit % 8);
+                let _ = fuzz_decompress(&corrupt);
+            }
+        }
+
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for len in 0..2000 {
+            let data = (0..len % 300)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect::<Vec<_>>();
+            let _ = fuzz_decompress(&data);
+        }
+    }
+}
@@ -1,27 +1,39 @@
 #![forbid(unsafe_code)]
 
-use std::io::BufRead;
+use std::io::{self, BufRead, Read};
 
 use anyhow::{bail, Context, Result};
 use byteorder::{LittleEndian, ReadBytesExt};
-use crc::Crc;
+use crc::{Crc, Digest};
+
+use crate::checksum::ALGORITHM;
+use crate::error::DecodeError;
 
 ////////////////////////////////////////////////////////////////////////////////
 
-const ID1: u8 = 0x1f;
-const ID2: u8 = 0x8b;
+pub(crate) const ID1: u8 = 0x1f;
+pub(crate) const ID2: u8 = 0x8b;
 
 const CM_DEFLATE: u8 = 8;
 
+const XFL_SLOWEST: u8 = 2;
+const XFL_FASTEST: u8 = 4;
+
 const FTEXT_OFFSET: u8 = 0;
 const FHCRC_OFFSET: u8 = 1;
 const FEXTRA_OFFSET: u8 = 2;
 const FNAME_OFFSET: u8 = 3;
 const FCOMMENT_OFFSET: u8 = 4;
+const FRESERVED_MASK: u8 = 0xe0;
+/// FLG bit 5, which gzip 0.x used to mark encrypted members.
+const FENCRYPTED: u8 = 0x20;
+
+const OS_LAST_KNOWN: u8 = 13;
+const OS_UNKNOWN: u8 = 255;
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct MemberHeader {
     pub compression_method: CompressionMethod,
     pub modification_time: u32,
@@ -32,9 +44,17 @@ pub struct MemberHeader {
     pub os: u8,
     pub has_crc: bool,
     pub is_text: bool,
+    /// FLG bits 6-7 as read. RFC 1952 requires them to be zero; bit 5, the
+    /// old encryption flag, is rejected while parsing.
+    pub reserved_flags: u8,
+    /// FNAME or FCOMMENT wasn't valid UTF-8 and was decoded lossily.
+    pub lossy_text: bool,
 }
 
 impl MemberHeader {
+    /// CRC16 of a header rebuilt from these fields. Parsing checks FHCRC
+    /// against the bytes actually read instead, which may differ (e.g. when
+    /// reserved flag bits are set).
     pub fn crc16(&self) -> u16 {
         let crc = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
         let mut digest = crc.digest();
@@ -61,6 +81,16 @@ impl MemberHeader {
         (digest.finalize() & 0xffff) as u16
     }
 
+    /// Compression level the producer reported in XFL, if any.
+    /// Only meaningful for DEFLATE members; `extra_flags` keeps the raw byte.
+    pub fn compression_hint(&self) -> Option<CompressionHint> {
+        match (self.compression_method, self.extra_flags) {
+            (CompressionMethod::Deflate, XFL_SLOWEST) => Some(CompressionHint::Slowest),
+            (CompressionMethod::Deflate, XFL_FASTEST) => Some(CompressionHint::Fastest),
+            _ => None,
+        }
+    }
+
     pub fn flags(&self) -> MemberFlags {
         let mut flags = MemberFlags(0);
         flags.set_is_text(self.is_text);
@@ -68,8 +98,68 @@ impl MemberHeader {
         flags.set_has_extra(self.extra.is_some());
         flags.set_has_name(self.name.is_some());
         flags.set_has_comment(self.comment.is_some());
+        flags.0 |= self.reserved_flags & FRESERVED_MASK;
         flags
     }
+
+    /// FEXTRA split into its subfields (RFC 1952, 2.3.1.1). Empty if the
+    /// member has no extra field.
+    ///
+    /// BGZF records each member's size in a `BC` subfield. pigz writes no
+    /// subfields at all; its chunk boundaries are flush markers inside the
+    /// DEFLATE stream, see [`BlockInfo::is_flush_marker`](crate::BlockInfo::is_flush_marker).
+    pub fn extra_subfields(&self) -> Result<Vec<ExtraSubfield>> {
+        match &self.extra {
+            Some(extra) => parse_extra_subfields(extra),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Whether OS is one of the values listed in RFC 1952, 255 included.
+    pub fn has_known_os(&self) -> bool {
+        self.os <= OS_LAST_KNOWN || self.os == OS_UNKNOWN
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// One SI1 SI2 LEN data entry of FEXTRA.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtraSubfield {
+    pub id: [u8; 2],
+    pub data: Vec<u8>,
+}
+
+pub(crate) fn parse_extra_subfields(mut extra: &[u8]) -> Result<Vec<ExtraSubfield>> {
+    let mut subfields = vec![];
+    while !extra.is_empty() {
+        let mut id = [0u8; 2];
+        extra.read_exact(&mut id).context("extra subfield id")?;
+        let len = extra
+            .read_u16::<LittleEndian>()
+            .context("extra subfield length")?;
+        if extra.len() < usize::from(len) {
+            bail!(DecodeError::UnexpectedEof);
+        }
+        let (data, rest) = extra.split_at(len.into());
+        subfields.push(ExtraSubfield {
+            id,
+            data: data.to_vec(),
+        });
+        extra = rest;
+    }
+    Ok(subfields)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// DEFLATE compression level hint stored in XFL.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionHint {
+    /// XFL = 2: maximum compression, slowest algorithm.
+    Slowest,
+    /// XFL = 4: fastest algorithm.
+    Fastest,
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -156,78 +246,179 @@ impl MemberFlags {
     pub fn set_has_comment(&mut self, value: bool) {
         self.set_bit(FCOMMENT_OFFSET, value)
     }
+
+    pub fn reserved(&self) -> u8 {
+        self.0 & FRESERVED_MASK
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug)]
+/// The CRC32 and ISIZE a member's footer declares for its decoded data,
+/// from [`GzipReader::read_footer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct MemberFooter {
     pub data_crc32: u32,
     pub data_size: u32,
 }
 
+/// How a member's footer compares to the data actually decoded, from
+/// [`DeflateReader::compare_footer`](crate::DeflateReader::compare_footer).
+#[must_use]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FooterCheck {
+    pub crc_ok: bool,
+    pub size_ok: bool,
+    pub computed_crc: u32,
+    /// Decoded length modulo 2^32, as ISIZE records it.
+    pub computed_size: u32,
+}
+
+impl FooterCheck {
+    pub fn is_ok(&self) -> bool {
+        self.crc_ok && self.size_ok
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Feeds every byte read from the header into a CRC, for the FHCRC check.
+struct HeaderReader<'a, T> {
+    reader: &'a mut T,
+    digest: Digest<'static, u32>,
+}
+
+impl<'a, T: BufRead> HeaderReader<'a, T> {
+    /// Start after ID1 and ID2, which are part of the checksummed bytes.
+    fn after_magic(reader: &'a mut T) -> Self {
+        let mut digest = ALGORITHM.digest();
+        digest.update(&[ID1, ID2]);
+        Self { reader, digest }
+    }
+
+    /// Read a NUL-terminated field, without the terminator.
+    fn read_zero_terminated(&mut self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        self.reader.read_until(0, &mut buffer)?;
+        self.digest.update(&buffer);
+        if buffer.pop() != Some(0) {
+            bail!(DecodeError::UnexpectedEof)
+        }
+        Ok(buffer)
+    }
+
+    fn crc16(self) -> u16 {
+        (self.digest.finalize() & 0xffff) as u16
+    }
+}
+
+impl<T: BufRead> Read for HeaderReader<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.digest.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// FNAME/FCOMMENT as a `String`. Invalid UTF-8 is an error unless
+/// `allow_lossy`, in which case it is replaced and `lossy` is set.
+fn decode_text(
+    buffer: Vec<u8>,
+    field: &str,
+    allow_lossy: bool,
+    lossy: &mut bool,
+) -> Result<String> {
+    match String::from_utf8(buffer) {
+        Ok(text) => Ok(text),
+        Err(error) if allow_lossy => {
+            *lossy = true;
+            Ok(String::from_utf8_lossy(error.as_bytes()).into_owned())
+        }
+        Err(error) => Err(error).with_context(|| format!("invalid UTF-8 in {}", field)),
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 pub struct GzipReader<T> {
     reader: T,
+    lossy_text: bool,
 }
 
 impl<T: BufRead> GzipReader<T> {
     pub fn new(reader: T) -> Self {
-        Self { reader }
+        Self {
+            reader,
+            lossy_text: false,
+        }
     }
 
-    pub fn parse_header(mut self) -> Result<()> {
+    /// Replace invalid UTF-8 in FNAME and FCOMMENT instead of failing.
+    pub fn lossy_text(mut self, value: bool) -> Self {
+        self.lossy_text = value;
+        self
+    }
+
+    pub fn parse_header(mut self) -> Result<MemberHeader> {
         let id1 = self.reader.read_u8()?;
         let id2 = self.reader.read_u8()?;
         if id1 != ID1 || id2 != ID2 {
-            bail!("wrong id values")
+            bail!(DecodeError::NotGzip { magic: [id1, id2] })
         }
+        self.parse_header_after_magic()
+    }
 
-        let cm = CompressionMethod::from(self.reader.read_u8().context("CM")?);
+    /// Parse the rest of a member header once ID1 and ID2 were consumed,
+    /// e.g. by [`GzipReader::skip_to_magic`].
+    pub fn parse_header_after_magic(mut self) -> Result<MemberHeader> {
+        let allow_lossy = self.lossy_text;
+        let mut reader = HeaderReader::after_magic(&mut self.reader);
+        let cm = CompressionMethod::from(reader.read_u8().context("CM")?);
 
-        let flg = MemberFlags(self.reader.read_u8().context("FLG")?);
-        let mtime = self.reader.read_u32::<LittleEndian>().context("MTIME")?;
-        let xfl = self.reader.read_u8().context("XFL")?;
-        let os = self.reader.read_u8().context("OS")?;
+        let flg = MemberFlags(reader.read_u8().context("FLG")?);
+        if flg.0 & FENCRYPTED != 0 {
+            bail!(DecodeError::UnsupportedFeature("encrypted member"));
+        }
+        let mtime = reader.read_u32::<LittleEndian>().context("MTIME")?;
+        let xfl = reader.read_u8().context("XFL")?;
+        let os = reader.read_u8().context("OS")?;
 
         let mut extra: Option<Vec<u8>> = None;
 
         if flg.has_extra() {
-            let xlen = self.reader.read_u16::<LittleEndian>().context("XLEN")?;
+            let xlen = reader.read_u16::<LittleEndian>().context("XLEN")?;
             let mut buffer: Vec<u8> = vec![0; xlen.into()];
-            self.reader
-                .read_exact(&mut buffer)
-                .context("extra read fail")?;
+            reader.read_exact(&mut buffer).context("extra read fail")?;
             extra = Some(buffer);
         }
 
         let mut name: Option<String> = None;
+        let mut lossy_text = false;
 
         if flg.has_name() {
-            let mut buffer: Vec<u8> = Vec::new();
-            self.reader
-                .read_until(0, &mut buffer)
-                .context("name read fail")?;
-            name = Some(String::from_utf8(buffer)?);
+            let buffer = reader.read_zero_terminated().context("name read fail")?;
+            name = Some(decode_text(buffer, "FNAME", allow_lossy, &mut lossy_text)?);
         }
 
         let mut comment: Option<String> = None;
 
         if flg.has_comment() {
-            let mut buffer: Vec<u8> = Vec::new();
-            self.reader
-                .read_until(0, &mut buffer)
-                .context("name read fail")?;
-            comment = Some(String::from_utf8(buffer)?);
+            let buffer = reader.read_zero_terminated().context("comment read fail")?;
+            comment = Some(decode_text(
+                buffer,
+                "FCOMMENT",
+                allow_lossy,
+                &mut lossy_text,
+            )?);
         }
 
-        let mut crc: bool = false;
-        let mut crc_value = 0;
-
-        if flg.has_crc() {
-            crc_value = self.reader.read_u16::<LittleEndian>().context("XLEN")?;
-            crc = true;
+        let crc: bool = flg.has_crc();
+        if crc {
+            let actual = reader.crc16();
+            let expected = self.reader.read_u16::<LittleEndian>().context("CRC16")?;
+            if expected != actual {
+                bail!(DecodeError::HeaderCrcMismatch { expected, actual })
+            }
         }
 
         let is_text: bool = flg.is_text();
@@ -242,27 +433,323 @@ impl<T: BufRead> GzipReader<T> {
             os,
             has_crc: crc,
             is_text,
+            reserved_flags: flg.reserved(),
+            lossy_text,
         };
 
-        if crc && member_header.crc16() != crc_value {
-            bail!("header crc16 check failed")
-        }
         match cm {
-            CompressionMethod::Deflate => Ok(()),
-            _ => bail!("unsupported compression method"),
+            CompressionMethod::Deflate => Ok(member_header),
+            CompressionMethod::Unknown(method) => bail!(DecodeError::UnsupportedMethod { method }),
         }
     }
 
-    pub fn read_crc32_and_isize(mut self) -> Result<(u32, u32)> {
-        Ok((
-            self.reader.read_u32::<LittleEndian>()?,
-            self.reader.read_u32::<LittleEndian>()?,
-        ))
+    /// Read the 8-byte footer that follows a member's DEFLATE data.
+    pub fn read_footer(mut self) -> Result<MemberFooter> {
+        Ok(MemberFooter {
+            data_crc32: self.reader.read_u32::<LittleEndian>().context("CRC32")?,
+            data_size: self.reader.read_u32::<LittleEndian>().context("ISIZE")?,
+        })
     }
 
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while let Err(error) = self.reader.fill_buf() {
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+        // Already filled, this just hands out the buffer.
+        self.reader.fill_buf()
+    }
+
+    /// Whether the input is at its end. Like the rest of `std::io`, this
+    /// takes an empty [`BufRead::fill_buf`] to mean end of stream, so a
+    /// reader must block until data arrives rather than return nothing
+    /// early. An [`io::ErrorKind::Interrupted`] read is retried.
     pub fn is_empty(&mut self) -> Result<bool> {
-        Ok(self.reader.fill_buf()?.is_empty())
+        Ok(self.fill_buf()?.is_empty())
+    }
+
+    /// Peek whether the input continues with a gzip magic. Only the bytes
+    /// already buffered are inspected, nothing is consumed. `None` if just
+    /// ID1 is buffered, since telling would take consuming it; see
+    /// [`GzipReader::read_magic`].
+    pub fn starts_with_magic(&mut self) -> Result<Option<bool>> {
+        Ok(match self.fill_buf()? {
+            [] => Some(false),
+            [id1] if *id1 == ID1 => None,
+            [_] => Some(false),
+            [id1, id2, ..] => Some(*id1 == ID1 && *id2 == ID2),
+        })
+    }
+
+    /// Consume a gzip magic if the input continues with one, however the
+    /// reader splits it, e.g. before [`GzipReader::parse_header_after_magic`].
+    /// Otherwise returns `false`, having consumed a leading ID1 if there was one.
+    pub fn read_magic(&mut self) -> Result<bool> {
+        for id in [ID1, ID2] {
+            match self.fill_buf()? {
+                [byte, ..] if *byte == id => self.reader.consume(1),
+                _ => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Consume input up to and including the next ID1 ID2 pair.
+    /// Returns `false` if the input ends before one is found.
+    pub fn skip_to_magic(&mut self) -> Result<bool> {
+        let mut seen_id1 = false;
+        loop {
+            let buffer = self.reader.fill_buf()?;
+            if buffer.is_empty() {
+                return Ok(false);
+            }
+            let len = buffer.len();
+            let mut found = None;
+            for (i, &byte) in buffer.iter().enumerate() {
+                if seen_id1 && byte == ID2 {
+                    found = Some(i + 1);
+                    break;
+                }
+                seen_id1 = byte == ID1;
+            }
+            match found {
+                Some(end) => {
+                    self.reader.consume(end);
+                    return Ok(true);
+                }
+                None => self.reader.consume(len),
+            }
+        }
     }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(header: &[u8]) -> Result<MemberHeader> {
+        GzipReader::new(header).parse_header()
+    }
+
+    /// Yields `hiccup` once before its data.
+    struct Hiccup<'a> {
+        hiccup: Option<io::ErrorKind>,
+        data: &'a [u8],
+    }
+
+    impl Read for Hiccup<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.fill_buf()?.len().min(buf.len());
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.consume(len);
+            Ok(len)
+        }
+    }
+
+    impl BufRead for Hiccup<'_> {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            match self.hiccup.take() {
+                Some(kind) => Err(kind.into()),
+                None => Ok(self.data),
+            }
+        }
+
+        fn consume(&mut self, amt: usize) {
+            self.data = &self.data[amt..];
+        }
+    }
+
+    #[test]
+    fn is_empty_retries_interrupted() -> Result<()> {
+        let data = [ID1, ID2];
+        let hiccup = |kind| Hiccup {
+            hiccup: Some(kind),
+            data: &data,
+        };
+        assert!(!GzipReader::new(hiccup(io::ErrorKind::Interrupted)).is_empty()?);
+        assert_eq!(
+            GzipReader::new(hiccup(io::ErrorKind::Interrupted)).starts_with_magic()?,
+            Some(true)
+        );
+        assert!(GzipReader::new(hiccup(io::ErrorKind::WouldBlock))
+            .is_empty()
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn magic_split_across_reads() -> Result<()> {
+        assert_eq!(GzipReader::new(&[ID1][..]).starts_with_magic()?, None);
+        assert_eq!(
+            GzipReader::new(&[ID1, 0][..]).starts_with_magic()?,
+            Some(false)
+        );
+        for data in [&[ID1, ID2, 8][..], &[ID1, 0], &[ID1], &[0, ID1]] {
+            let mut whole = data;
+            let mut split = io::BufReader::with_capacity(1, data);
+            let found = GzipReader::new(&mut whole).read_magic()?;
+            assert_eq!(GzipReader::new(&mut split).read_magic()?, found);
+            assert_eq!(found, data.starts_with(&[ID1, ID2]));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn not_gzip() {
+        let message = |data: &[u8]| parse(data).unwrap_err().to_string();
+        assert_eq!(
+            message(&[0x78, 0x9c, 0]),
+            "expected gzip magic 1f 8b, got 78 9c (looks like zlib, try decompress_auto)"
+        );
+        assert_eq!(
+            message(b"hello"),
+            "expected gzip magic 1f 8b, got 68 65 (looks like uncompressed text)"
+        );
+        assert_eq!(
+            parse(&[ID1, 0]).unwrap_err().downcast_ref::<DecodeError>(),
+            Some(&DecodeError::NotGzip { magic: [ID1, 0] })
+        );
+    }
+
+    #[test]
+    fn read_footer() -> Result<()> {
+        let footer = GzipReader::new(&[0x78, 0x56, 0x34, 0x12, 5, 0, 0, 0][..]).read_footer()?;
+        assert_eq!(
+            footer,
+            MemberFooter {
+                data_crc32: 0x12345678,
+                data_size: 5,
+            }
+        );
+        let error = GzipReader::new(&[0; 6][..]).read_footer().unwrap_err();
+        assert_eq!(error.to_string(), "ISIZE");
+        Ok(())
+    }
+
+    #[test]
+    fn compression_hint() -> Result<()> {
+        let header = parse(&[ID1, ID2, CM_DEFLATE, 0, 0, 0, 0, 0, 2, 3])?;
+        assert_eq!(header.compression_hint(), Some(CompressionHint::Slowest));
+        let header = parse(&[ID1, ID2, CM_DEFLATE, 0, 0, 0, 0, 0, 4, 3])?;
+        assert_eq!(header.compression_hint(), Some(CompressionHint::Fastest));
+        let header = parse(&[ID1, ID2, CM_DEFLATE, 0, 0, 0, 0, 0, 0, 3])?;
+        assert_eq!(header.compression_hint(), None);
+        assert_eq!(header.extra_flags, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn header_crc_over_wire_bytes() -> Result<()> {
+        // FHCRC | FNAME plus reserved bit 6, which the fields can't reproduce.
+        let mut header = vec![ID1, ID2, CM_DEFLATE, 0x4a, 0, 0, 0, 0, 0, 3, b'a', 0];
+        header.extend(0x2bebu16.to_le_bytes());
+        assert!(parse(&header)?.has_crc);
+
+        header[10] = b'b';
+        let error = parse(&header).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::HeaderCrcMismatch {
+                expected: 0x2beb,
+                actual: ALGORITHM.checksum(&header[..12]) as u16,
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn missing_nul_terminator() {
+        let header = [ID1, ID2, CM_DEFLATE, 0x08, 0, 0, 0, 0, 0, 3, b'a', b'b'];
+        let error = parse(&header).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn name_and_comment_without_nul() -> Result<()> {
+        let mut header = vec![ID1, ID2, CM_DEFLATE, 0x1a, 0, 0, 0, 0, 0, 3];
+        header.extend(b"test\0hi\0");
+        header.extend(0x2622u16.to_le_bytes());
+        let header = parse(&header)?;
+        assert_eq!(header.name, Some("test".into()));
+        assert_eq!(header.comment, Some("hi".into()));
+        assert_eq!(header.crc16(), 0x2622);
+        Ok(())
+    }
+
+    #[test]
+    fn empty_optional_fields() -> Result<()> {
+        // FHCRC | FEXTRA | FNAME | FCOMMENT, with XLEN = 0 and bare NULs.
+        let mut header = vec![ID1, ID2, CM_DEFLATE, 0x1e, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0];
+        let crc16 = ALGORITHM.checksum(&header) as u16;
+        header.extend(crc16.to_le_bytes());
+        let header = parse(&header)?;
+        assert_eq!(header.extra, Some(vec![]));
+        assert_eq!(header.name, Some(String::new()));
+        assert_eq!(header.comment, Some(String::new()));
+        assert_eq!(header.extra_subfields()?, vec![]);
+        let flags = header.flags();
+        assert!(flags.has_extra() && flags.has_name() && flags.has_comment());
+        assert_eq!(header.crc16(), crc16);
+
+        let header = parse(&[ID1, ID2, CM_DEFLATE, 0, 0, 0, 0, 0, 0, 3])?;
+        assert_eq!(
+            (header.extra, header.name, header.comment),
+            (None, None, None)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reserved_flags_and_os() -> Result<()> {
+        let header = parse(&[ID1, ID2, CM_DEFLATE, 0xc0, 0, 0, 0, 0, 0, 3])?;
+        assert_eq!(header.reserved_flags, 0xc0);
+        assert_eq!(header.flags().0, 0xc0);
+        assert!(header.has_known_os());
+
+        let header = parse(&[ID1, ID2, CM_DEFLATE, 0, 0, 0, 0, 0, 0, 14])?;
+        assert_eq!(header.reserved_flags, 0);
+        assert!(!header.has_known_os());
+        let header = parse(&[ID1, ID2, CM_DEFLATE, 0, 0, 0, 0, 0, 0, 255])?;
+        assert!(header.has_known_os());
+        Ok(())
+    }
+
+    #[test]
+    fn encrypted() {
+        let error = parse(&[ID1, ID2, CM_DEFLATE, 0x20, 0, 0, 0, 0, 0, 3]).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::UnsupportedFeature("encrypted member"))
+        );
+    }
+
+    #[test]
+    fn extra_subfields() -> Result<()> {
+        let mut header = vec![ID1, ID2, CM_DEFLATE, 0x04, 0, 0, 0, 0, 0, 3, 11, 0];
+        header.extend([b'B', b'C', 2, 0, 0x1b, 0, b'A', b'p', 1, 0, 7]);
+        let header = parse(&header)?;
+        assert_eq!(
+            header.extra_subfields()?,
+            vec![
+                ExtraSubfield {
+                    id: *b"BC",
+                    data: vec![0x1b, 0],
+                },
+                ExtraSubfield {
+                    id: *b"Ap",
+                    data: vec![7],
+                },
+            ]
+        );
+
+        assert!(parse_extra_subfields(&[b'B', b'C', 2, 0, 0x1b]).is_err());
+        assert!(parse_extra_subfields(b"B").is_err());
+        Ok(())
+    }
+}
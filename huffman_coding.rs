@@ -1,10 +1,17 @@
 #![forbid(unsafe_code)]
 
-use std::{collections::HashMap, convert::TryFrom, io::BufRead};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt,
+    io::BufRead,
+    sync::{Arc, OnceLock},
+};
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{bail, Result};
 
 use crate::bit_reader::{BitReader, BitSequence};
+use crate::error::DecodeError;
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -12,90 +19,92 @@ const SPECIAL_ORDER: [usize; 19] = [
     16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
 ];
 
+const MAX_LITLEN_CODES: u16 = 286;
+const MAX_DISTANCE_CODES: u16 = 32;
+
+/// Base lengths and extra bits of length symbols 257..=285 (RFC 1951, 3.2.5).
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Base distances and extra bits of distance symbols 0..=29.
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Code lengths of the fixed literal/length code (RFC 1951, 3.2.6).
+pub fn fixed_litlen_lengths() -> [u8; 288] {
+    let mut lengths = [8u8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths
+}
+
+/// The fixed trees, built on first use and shared from then on.
 pub fn decode_fixed_trees() -> Result<(HuffmanCoding<LitLenToken>, HuffmanCoding<DistanceToken>)> {
-    let distancetoken = [5u8; 32];
-    let mut letlentoken = vec![];
-    letlentoken.extend([8u8; 144]);
-    letlentoken.extend([9u8; 112]);
-    letlentoken.extend([7u8; 24]);
-    letlentoken.extend([8u8; 8]);
-    Ok((
-        HuffmanCoding::from_lengths(&letlentoken)?,
-        HuffmanCoding::from_lengths(&distancetoken)?,
-    ))
+    static FIXED: OnceLock<(HuffmanCoding<LitLenToken>, HuffmanCoding<DistanceToken>)> =
+        OnceLock::new();
+    if let Some(trees) = FIXED.get() {
+        return Ok(trees.clone());
+    }
+    let trees = (
+        HuffmanCoding::from_lengths(&fixed_litlen_lengths())?,
+        HuffmanCoding::from_lengths(&[5u8; 32])?,
+    );
+    Ok(FIXED.get_or_init(|| trees).clone())
 }
 
 pub fn decode_codelen_token<T: BufRead>(
     bit_reader: &mut BitReader<T>,
     hclen: u16,
-) -> Result<HuffmanCoding<TreeCodeToken>> {
+) -> Result<CodeLengthCoding> {
     let mut cl: Vec<u8> = vec![0; 19];
     for pos in &SPECIAL_ORDER[..(hclen + 4).into()] {
         cl[*pos] = bit_reader.read_bits(3)?.bits() as u8;
     }
-    HuffmanCoding::from_lengths(&cl)
+    CodeLengthCoding::from_lengths(&cl)
 }
 
-pub fn decode_letlen_token<T: BufRead>(
+/// Read `count` code lengths. Literal/length and distance lengths form one
+/// sequence (RFC 1951, 3.2.7), so a repeat may run across the two tables.
+pub fn decode_code_lengths<T: BufRead>(
     bit_reader: &mut BitReader<T>,
-    hlit: u16,
-    cl_huffman: &HuffmanCoding<TreeCodeToken>,
-) -> Result<HuffmanCoding<LitLenToken>> {
-    let mut letlentoken: Vec<u8> = vec![0; 286];
-    let mut pos: usize = 0;
-    while pos < (hlit + 257).into() {
-        let token = cl_huffman.read_symbol(bit_reader)?;
-        match token {
-            TreeCodeToken::Length(len) => {
-                letlentoken[pos] = len;
-                pos += 1;
-            }
+    count: usize,
+    cl_huffman: &CodeLengthCoding,
+) -> Result<Vec<u8>> {
+    let mut lengths: Vec<u8> = Vec::with_capacity(count);
+    while lengths.len() < count {
+        let (value, repeat) = match cl_huffman.read_symbol(bit_reader)? {
+            TreeCodeToken::Length(len) => (len, 1),
             TreeCodeToken::CopyPrev => {
-                for i in 0..(3 + bit_reader.read_bits(2)?.bits()).into() {
-                    letlentoken[pos] = letlentoken[pos - i - 1];
-                    pos += 1;
-                }
+                let Some(&prev) = lengths.last() else {
+                    bail!(DecodeError::InvalidHuffmanCode(
+                        "repeat with no previous length"
+                    ));
+                };
+                (prev, 3 + bit_reader.read_bits(2)?.bits())
             }
             TreeCodeToken::RepeatZero { base, extra_bits } => {
-                for _i in 0..(bit_reader.read_bits(extra_bits)?.bits() + base).into() {
-                    letlentoken[pos] = 0;
-                    pos += 1;
-                }
-            }
-        }
-    }
-    HuffmanCoding::from_lengths(&letlentoken)
-}
-
-pub fn decode_distance_token<T: BufRead>(
-    bit_reader: &mut BitReader<T>,
-    hdist: u16,
-    cl_huffman: &HuffmanCoding<TreeCodeToken>,
-) -> Result<HuffmanCoding<DistanceToken>> {
-    let mut distancetoken: Vec<u8> = vec![0; 32];
-    let mut pos: usize = 0;
-    while pos < (hdist + 1).into() {
-        let token = cl_huffman.read_symbol(bit_reader)?;
-        match token {
-            TreeCodeToken::Length(len) => {
-                distancetoken[pos] = len;
-                pos += 1;
-            }
-            TreeCodeToken::CopyPrev => {
-                for i in 0..(3 + bit_reader.read_bits(2)?.bits()).into() {
-                    distancetoken[pos] = distancetoken[pos - i - 1];
-                    pos += 1;
-                }
-            }
-            TreeCodeToken::RepeatZero { base, extra_bits } => {
-                for _i in 0..(bit_reader.read_bits(extra_bits)?.bits() + base).into() {
-                    distancetoken[pos] = 0;
-                    pos += 1;
-                }
+                (0, bit_reader.read_bits(extra_bits)?.bits() + base)
             }
+        };
+        if lengths.len() + usize::from(repeat) > count {
+            bail!(DecodeError::InvalidHuffmanCode(
+                "repeat past the end of the code lengths"
+            ));
         }
+        lengths.resize(lengths.len() + usize::from(repeat), value);
     }
-    HuffmanCoding::from_lengths(&distancetoken)
+    Ok(lengths)
 }
 
 pub fn decode_dynamic_tree<T: BufRead>(
@@ -105,11 +114,31 @@ pub fn decode_dynamic_tree<T: BufRead>(
     let hdist = bit_reader.read_bits(5)?.bits();
     let hclen = bit_reader.read_bits(4)?.bits();
 
+    if hlit + 257 > MAX_LITLEN_CODES {
+        bail!(DecodeError::InvalidHuffmanCode(
+            "too many literal/length codes"
+        ));
+    }
+    if hdist + 1 > MAX_DISTANCE_CODES {
+        bail!(DecodeError::InvalidHuffmanCode("too many distance codes"));
+    }
+
     let cl_huffman = decode_codelen_token(bit_reader, hclen)?;
-    let letlentoken = decode_letlen_token(bit_reader, hlit, &cl_huffman)?;
-    let distancetoken = decode_distance_token(bit_reader, hdist, &cl_huffman)?;
+    let hlit = usize::from(hlit + 257);
+    let lengths = decode_code_lengths(bit_reader, hlit + usize::from(hdist + 1), &cl_huffman)?;
+    let (letlen, distance) = lengths.split_at(hlit);
 
-    Ok((letlentoken, distancetoken))
+    Ok((
+        HuffmanCoding::from_lengths(letlen)?,
+        HuffmanCoding::from_lengths(distance)?,
+    ))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// The symbol set a Huffman code is over, named in errors.
+pub trait Alphabet {
+    const NAME: &'static str;
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -121,6 +150,10 @@ pub enum TreeCodeToken {
     RepeatZero { base: u16, extra_bits: u8 },
 }
 
+impl Alphabet for TreeCodeToken {
+    const NAME: &'static str = "code length";
+}
+
 impl TryFrom<HuffmanCodeWord> for TreeCodeToken {
     type Error = anyhow::Error;
 
@@ -136,7 +169,11 @@ impl TryFrom<HuffmanCodeWord> for TreeCodeToken {
                 base: 11,
                 extra_bits: 7,
             }),
-            _ => Err(anyhow!("try from TreeCodeToken error")),
+            value => Err(DecodeError::InvalidSymbol {
+                alphabet: Self::NAME,
+                value,
+            }
+            .into()),
         }
     }
 }
@@ -150,42 +187,29 @@ pub enum LitLenToken {
     Length { base: u16, extra_bits: u8 },
 }
 
+impl Alphabet for LitLenToken {
+    const NAME: &'static str = "literal/length";
+}
+
 impl TryFrom<HuffmanCodeWord> for LitLenToken {
     type Error = anyhow::Error;
 
     fn try_from(value: HuffmanCodeWord) -> Result<Self> {
         match value.0 {
-            0..=255 => Ok(LitLenToken::Literal(value.0.try_into().unwrap())),
+            0..=255 => Ok(LitLenToken::Literal(value.0 as u8)),
             256 => Ok(LitLenToken::EndOfBlock),
-            257..=264 => Ok(LitLenToken::Length {
-                base: value.0 - 254,
-                extra_bits: 0,
-            }),
-            265..=268 => Ok(LitLenToken::Length {
-                base: 11 + 2 * (value.0 - 265),
-                extra_bits: 1,
-            }),
-            269..=272 => Ok(LitLenToken::Length {
-                base: 19 + 4 * (value.0 - 269),
-                extra_bits: 2,
-            }),
-            273..=276 => Ok(LitLenToken::Length {
-                base: 35 + 8 * (value.0 - 273),
-                extra_bits: 3,
-            }),
-            277..=280 => Ok(LitLenToken::Length {
-                base: 67 + 16 * (value.0 - 277),
-                extra_bits: 4,
-            }),
-            281..=284 => Ok(LitLenToken::Length {
-                base: 131 + 32 * (value.0 - 281),
-                extra_bits: 5,
-            }),
-            285 => Ok(LitLenToken::Length {
-                base: 258,
-                extra_bits: 0,
-            }),
-            _ => Err(anyhow!("try from LitLenToken error")),
+            symbol @ 257..=285 => {
+                let index = usize::from(symbol - 257);
+                Ok(LitLenToken::Length {
+                    base: LENGTH_BASE[index],
+                    extra_bits: LENGTH_EXTRA[index],
+                })
+            }
+            value => Err(DecodeError::InvalidSymbol {
+                alphabet: Self::NAME,
+                value,
+            }
+            .into()),
         }
     }
 }
@@ -198,68 +222,18 @@ pub struct DistanceToken {
     pub extra_bits: u8,
 }
 
+impl Alphabet for DistanceToken {
+    const NAME: &'static str = "distance";
+}
+
 impl TryFrom<HuffmanCodeWord> for DistanceToken {
     type Error = anyhow::Error;
 
     fn try_from(value: HuffmanCodeWord) -> Result<Self> {
-        match value.0 {
-            0..=3 => Ok(DistanceToken {
-                base: value.0 + 1,
-                extra_bits: 0,
-            }),
-            4..=5 => Ok(DistanceToken {
-                base: 5 + 2 * (value.0 - 4),
-                extra_bits: 1,
-            }),
-            6..=7 => Ok(DistanceToken {
-                base: 9 + 4 * (value.0 - 6),
-                extra_bits: 2,
-            }),
-            8..=9 => Ok(DistanceToken {
-                base: 17 + 8 * (value.0 - 8),
-                extra_bits: 3,
-            }),
-            10..=11 => Ok(DistanceToken {
-                base: 33 + 16 * (value.0 - 10),
-                extra_bits: 4,
-            }),
-            12..=13 => Ok(DistanceToken {
-                base: 65 + 32 * (value.0 - 12),
-                extra_bits: 5,
-            }),
-            14..=15 => Ok(DistanceToken {
-                base: 129 + 64 * (value.0 - 14),
-                extra_bits: 6,
-            }),
-            16..=17 => Ok(DistanceToken {
-                base: 257 + 128 * (value.0 - 16),
-                extra_bits: 7,
-            }),
-            18..=19 => Ok(DistanceToken {
-                base: 513 + 256 * (value.0 - 18),
-                extra_bits: 8,
-            }),
-            20..=21 => Ok(DistanceToken {
-                base: 1025 + 512 * (value.0 - 20),
-                extra_bits: 9,
-            }),
-            22..=23 => Ok(DistanceToken {
-                base: 2049 + 1024 * (value.0 - 22),
-                extra_bits: 10,
-            }),
-            24..=25 => Ok(DistanceToken {
-                base: 4097 + 2048 * (value.0 - 24),
-                extra_bits: 11,
-            }),
-            26..=27 => Ok(DistanceToken {
-                base: 8193 + 4096 * (value.0 - 26),
-                extra_bits: 12,
-            }),
-            28..=29 => Ok(DistanceToken {
-                base: 16385 + 8192 * (value.0 - 28),
-                extra_bits: 13,
-            }),
-            _ => Err(anyhow!("try from DistanceToken error")),
+        let index = usize::from(value.0);
+        match (DIST_BASE.get(index), DIST_EXTRA.get(index)) {
+            (Some(&base), Some(&extra_bits)) => Ok(DistanceToken { base, extra_bits }),
+            _ => Err(DecodeError::InvalidDistanceSymbol { symbol: value.0 }.into()),
         }
     }
 }
@@ -270,84 +244,290 @@ const MAX_BITS: usize = 15;
 
 pub struct HuffmanCodeWord(pub u16);
 
+/// A decoding table. Cloning shares the table instead of copying it, so
+/// one tree can be handed to many blocks or threads.
 pub struct HuffmanCoding<T> {
+    tables: Arc<Tables<T>>,
+}
+
+struct Tables<T> {
     map: HashMap<BitSequence, T>,
+    /// Codes of symbols `T` rejects, e.g. distance symbols 30 and 31.
+    invalid: HashMap<BitSequence, u16>,
+    /// Longest code, past which no symbol can match.
+    max_len: u8,
+}
+
+impl<T> Clone for HuffmanCoding<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tables: Arc::clone(&self.tables),
+        }
+    }
 }
 
 impl<T> HuffmanCoding<T>
 where
-    T: Copy + TryFrom<HuffmanCodeWord, Error = anyhow::Error> + std::fmt::Debug,
+    T: Alphabet + Copy + TryFrom<HuffmanCodeWord, Error = anyhow::Error> + std::fmt::Debug,
 {
-    pub fn new(map: HashMap<BitSequence, T>) -> Self {
-        Self { map }
-    }
-
     #[allow(unused)]
     pub fn decode_symbol(&self, seq: BitSequence) -> Option<T> {
-        self.map.get(&seq).copied()
+        self.tables.map.get(&seq).copied()
     }
 
     pub fn read_symbol<U: BufRead>(&self, bit_reader: &mut BitReader<U>) -> Result<T> {
         let mut bit_sequence = BitSequence::new(0, 0);
-        for _i in 0..MAX_BITS {
+        let tables = &*self.tables;
+        for _i in 0..tables.max_len {
             let bit = bit_reader.read_bits(1)?;
             bit_sequence = bit_sequence.concat(bit);
 
-            if let Some(&value) = self.map.get(&bit_sequence) {
+            if let Some(&value) = tables.map.get(&bit_sequence) {
                 return Ok(value);
             }
+            if let Some(&symbol) = tables.invalid.get(&bit_sequence) {
+                return T::try_from(HuffmanCodeWord(symbol));
+            }
         }
-        bail!("read_symbol 2 type error")
+        bail!(DecodeError::UnassignedCode { alphabet: T::NAME })
     }
 
     pub fn from_lengths(code_lengths: &[u8]) -> Result<Self> {
-        // algo from rfc
-        let mut bl_count: [usize; MAX_BITS + 1] = [0; MAX_BITS + 1];
-        let mut next_code: [usize; MAX_BITS + 1] = [0; MAX_BITS + 1];
+        let used = code_lengths.iter().filter(|&&len| len != 0).count();
+        let mut map = HashMap::with_capacity(used);
+        let mut invalid = HashMap::new();
+        for (n, code) in canonical_codes(code_lengths)?.into_iter().enumerate() {
+            // Symbols like literal/length 286, 287 get a code but must never
+            // be used. Reading one fails with the `TryFrom` error.
+            let Some(code) = code else { continue };
+            match T::try_from(HuffmanCodeWord(n as u16)) {
+                Ok(value) => {
+                    map.insert(code, value);
+                }
+                Err(_) => {
+                    invalid.insert(code, n as u16);
+                }
+            }
+        }
+        Ok(HuffmanCoding {
+            tables: Arc::new(Tables {
+                map,
+                invalid,
+                max_len: code_lengths.iter().copied().max().unwrap_or(0),
+            }),
+        })
+    }
+}
+
+impl<T> HuffmanCoding<T> {
+    /// Number of symbols that have a code.
+    pub fn len(&self) -> usize {
+        self.tables.map.len()
+    }
+}
+
+/// Lists the codes shortest first, then by code value.
+impl<T: fmt::Debug> fmt::Debug for HuffmanCoding<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut entries = Vec::with_capacity(self.len());
+        entries.extend(self.tables.map.iter());
+        entries.sort_by_key(|(code, _)| (code.len(), code.bits()));
+        f.debug_map().entries(entries).finish()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Longest code of the code length alphabet, whose lengths are 3-bit fields.
+const MAX_CODELEN_BITS: u8 = 7;
 
-        for &len in code_lengths {
-            if usize::from(len) > MAX_BITS {
+/// Decoder for the 19-symbol code length alphabet. It is built for every
+/// dynamic block, so instead of a `HashMap` it indexes a flat table by the
+/// code read so far with a leading 1 bit marking its length.
+pub struct CodeLengthCoding {
+    table: [Option<TreeCodeToken>; 2 << MAX_CODELEN_BITS],
+}
+
+impl CodeLengthCoding {
+    /// Unlike the other two alphabets, this code must be complete: every
+    /// 7-bit string has to start with some code, as zlib requires too.
+    pub fn from_lengths(code_lengths: &[u8]) -> Result<Self> {
+        let kraft_sum: u32 = code_lengths
+            .iter()
+            .filter(|&&len| len != 0)
+            .map(|&len| 1 << (MAX_CODELEN_BITS - len.min(MAX_CODELEN_BITS)))
+            .sum();
+        if kraft_sum != 1 << MAX_CODELEN_BITS {
+            bail!(DecodeError::InvalidCodeLengthTable);
+        }
+        let mut table = [None; 2 << MAX_CODELEN_BITS];
+        for (n, code) in canonical_codes(code_lengths)?.into_iter().enumerate() {
+            let Some(code) = code else { continue };
+            if code.len() > MAX_CODELEN_BITS {
                 bail!("from_lengths error")
             }
-            bl_count[usize::from(len)] += 1;
+            table[1 << code.len() | usize::from(code.bits())] =
+                Some(TreeCodeToken::try_from(HuffmanCodeWord(n as u16))?);
         }
+        Ok(Self { table })
+    }
 
-        let mut code = 0;
-        bl_count[0] = 0;
-        for bits in 1..=MAX_BITS {
-            code = (code + bl_count[bits - 1]) << 1;
-            next_code[bits] = code;
+    pub fn read_symbol<U: BufRead>(&self, bit_reader: &mut BitReader<U>) -> Result<TreeCodeToken> {
+        let mut index = 1;
+        for _ in 0..MAX_CODELEN_BITS {
+            index = index << 1 | usize::from(bit_reader.read_bits(1)?.bits());
+            if let Some(token) = self.table[index] {
+                return Ok(token);
+            }
         }
+        bail!(DecodeError::UnassignedCode {
+            alphabet: TreeCodeToken::NAME
+        })
+    }
+}
 
-        let mut map = HashMap::new();
-        let mut n = 0;
-        for &len in code_lengths {
-            if len == 0 {
-                n += 1;
-                continue;
-            }
-            let value = T::try_from(HuffmanCodeWord(n))?;
-            map.insert(
-                BitSequence::new(next_code[usize::from(len)].try_into().unwrap(), len),
-                value,
+////////////////////////////////////////////////////////////////////////////////
+
+/// Symbol to code direction of a canonical Huffman code, for encoding.
+pub struct HuffmanEncoder {
+    codes: Vec<Option<BitSequence>>,
+}
+
+impl HuffmanEncoder {
+    /// Assign codes exactly like [`HuffmanCoding::from_lengths`] does.
+    pub fn from_lengths(code_lengths: &[u8]) -> Result<Self> {
+        Ok(Self {
+            codes: canonical_codes(code_lengths)?,
+        })
+    }
+
+    /// Code of `symbol`, most significant bit first, or `None` if the symbol
+    /// has no code.
+    pub fn encode(&self, symbol: u16) -> Option<BitSequence> {
+        self.codes.get(usize::from(symbol)).copied().flatten()
+    }
+
+    /// Optimal code lengths of at most `max_len` bits for symbols occurring
+    /// `freqs` times, ready for [`HuffmanEncoder::from_lengths`]. Unused
+    /// symbols get length 0, and a lone used symbol gets a one-bit code.
+    ///
+    /// Uses package-merge. Fails if `max_len` is over 15 or too short to
+    /// give every used symbol a code.
+    pub fn lengths_from_frequencies(freqs: &[u32], max_len: u8) -> Result<Vec<u8>> {
+        let mut leaves: Vec<(u64, Vec<u16>)> = freqs
+            .iter()
+            .enumerate()
+            .filter(|&(_, &freq)| freq != 0)
+            .map(|(symbol, &freq)| (u64::from(freq), vec![symbol as u16]))
+            .collect();
+        leaves.sort_by_key(|(weight, symbols)| (*weight, symbols[0]));
+
+        let mut lengths = vec![0; freqs.len()];
+        if usize::from(max_len) > MAX_BITS || leaves.len() > 1 << max_len {
+            bail!(
+                "can't code {} symbols in at most {} bits",
+                leaves.len(),
+                max_len
             );
+        }
+        match leaves.as_slice() {
+            [] => return Ok(lengths),
+            [(_, symbols)] => {
+                lengths[usize::from(symbols[0])] = 1;
+                return Ok(lengths);
+            }
+            _ => {}
+        }
 
-            next_code[usize::from(len)] += 1;
-            n += 1;
+        // Each round pairs up the previous list into packages one level
+        // deeper and merges them with the leaves.
+        let mut list = leaves.clone();
+        for _ in 1..max_len {
+            let packages = list.chunks_exact(2).map(|pair| {
+                let symbols = [pair[0].1.as_slice(), pair[1].1.as_slice()].concat();
+                (pair[0].0 + pair[1].0, symbols)
+            });
+            let mut merged = Vec::with_capacity(leaves.len() + list.len() / 2);
+            let mut leaves = leaves.iter().cloned().peekable();
+            for package in packages {
+                while let Some(leaf) = leaves.next_if(|leaf| leaf.0 <= package.0) {
+                    merged.push(leaf);
+                }
+                merged.push(package);
+            }
+            merged.extend(leaves);
+            list = merged;
+        }
+
+        // A symbol's length is how often it shows up in the cheapest items.
+        for (_, symbols) in &list[..2 * leaves.len() - 2] {
+            for &symbol in symbols {
+                lengths[usize::from(symbol)] += 1;
+            }
         }
-        Ok(HuffmanCoding::new(map))
+        Ok(lengths)
     }
 }
 
+/// Canonical code for every symbol (RFC 1951, 3.2.2), `None` for length 0.
+fn canonical_codes(code_lengths: &[u8]) -> Result<Vec<Option<BitSequence>>> {
+    // algo from rfc
+    let mut bl_count: [usize; MAX_BITS + 1] = [0; MAX_BITS + 1];
+    let mut next_code: [usize; MAX_BITS + 1] = [0; MAX_BITS + 1];
+
+    for &len in code_lengths {
+        if usize::from(len) > MAX_BITS {
+            bail!("from_lengths error")
+        }
+        bl_count[usize::from(len)] += 1;
+    }
+
+    bl_count[0] = 0;
+    // Only over-subscription is an error. Incomplete codes are accepted,
+    // which covers RFC 1951's single one-bit distance code.
+    let mut left: isize = 1;
+    for &count in &bl_count[1..] {
+        left = (left << 1) - count as isize;
+        if left < 0 {
+            bail!(DecodeError::InvalidHuffmanCode("over-subscribed code"));
+        }
+    }
+
+    let mut code = 0;
+    for bits in 1..=MAX_BITS {
+        code = (code + bl_count[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+
+    let mut codes = Vec::with_capacity(code_lengths.len());
+    for &len in code_lengths {
+        if len == 0 {
+            codes.push(None);
+            continue;
+        }
+        codes.push(Some(BitSequence::new(
+            next_code[usize::from(len)] as u16,
+            len,
+        )));
+        next_code[usize::from(len)] += 1;
+    }
+    Ok(codes)
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bit_writer::BitWriter;
 
     #[derive(Clone, Copy, Debug, PartialEq)]
     struct Value(u16);
 
+    impl Alphabet for Value {
+        const NAME: &'static str = "test";
+    }
+
     impl TryFrom<HuffmanCodeWord> for Value {
         type Error = anyhow::Error;
 
@@ -356,6 +536,109 @@ mod tests {
         }
     }
 
+    #[test]
+    fn debug_sorted_by_length() -> Result<()> {
+        let code = HuffmanCoding::<Value>::from_lengths(&[2, 0, 1])?;
+        assert_eq!(code.len(), 2);
+        assert_eq!(
+            format!("{:?}", code),
+            "{BitSequence { bits: 0, len: 1 }: Value(2), \
+             BitSequence { bits: 2, len: 2 }: Value(0)}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn code_length_coding() -> Result<()> {
+        // Symbols 0, 16 and 18 get codes 0, 10 and 11; the rest are unused.
+        let mut lengths = [0; 19];
+        lengths[0] = 1;
+        lengths[16] = 2;
+        lengths[18] = 2;
+        let code = CodeLengthCoding::from_lengths(&lengths)?;
+        let data = [0b0001_1010u8];
+        let mut reader = BitReader::new(data.as_slice());
+        assert!(matches!(
+            code.read_symbol(&mut reader)?,
+            TreeCodeToken::Length(0)
+        ));
+        assert!(matches!(
+            code.read_symbol(&mut reader)?,
+            TreeCodeToken::CopyPrev
+        ));
+        assert!(matches!(
+            code.read_symbol(&mut reader)?,
+            TreeCodeToken::RepeatZero {
+                base: 11,
+                extra_bits: 7
+            }
+        ));
+        assert_eq!(reader.bit_position(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn incomplete_code_length_table() -> Result<()> {
+        let invalid = |lengths: &[u8]| {
+            let error = CodeLengthCoding::from_lengths(lengths).err();
+            error.and_then(|error| error.downcast_ref::<DecodeError>().cloned())
+        };
+        assert_eq!(invalid(&[1]), Some(DecodeError::InvalidCodeLengthTable));
+        assert_eq!(invalid(&[0; 19]), Some(DecodeError::InvalidCodeLengthTable));
+        assert_eq!(
+            invalid(&[1, 2, 2, 2]),
+            Some(DecodeError::InvalidCodeLengthTable)
+        );
+        assert_eq!(invalid(&[1, 1]), None);
+
+        // HLIT = 257, HDIST = 1, HCLEN = 4 with only symbol 16 given a code.
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(BitSequence::new(0, 14))?;
+        writer.write_bits(BitSequence::new(1, 3))?;
+        writer.write_bits(BitSequence::new(0, 9))?;
+        let data = writer.borrow_writer_from_boundary()?.clone();
+        let error = decode_dynamic_tree(&mut BitReader::new(data.as_slice())).err();
+        assert_eq!(
+            error.and_then(|error| error.downcast_ref::<DecodeError>().cloned()),
+            Some(DecodeError::InvalidCodeLengthTable)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn shared_tables() -> Result<()> {
+        let (litlen, distance) = decode_fixed_trees()?;
+        let (again, _) = decode_fixed_trees()?;
+        assert!(Arc::ptr_eq(&litlen.tables, &again.tables));
+
+        // `read_symbol` takes `&self`, so clones can decode on other threads.
+        let data = [0b0000_1100u8];
+        let handles = (0..2).map(|_| {
+            let litlen = litlen.clone();
+            std::thread::spawn(move || {
+                let mut reader = BitReader::new(data.as_slice());
+                matches!(litlen.read_symbol(&mut reader), Ok(LitLenToken::Literal(0)))
+            })
+        });
+        for handle in handles.collect::<Vec<_>>() {
+            assert!(handle.join().unwrap());
+        }
+        assert_eq!(distance.len(), 30);
+        Ok(())
+    }
+
+    #[test]
+    fn single_symbol() -> Result<()> {
+        let code = HuffmanCoding::<Value>::from_lengths(&[0, 0, 1])?;
+        assert_eq!(code.len(), 1);
+        let data = [0b10u8, 0, 0];
+        let mut reader = BitReader::new(data.as_slice());
+        assert_eq!(code.read_symbol(&mut reader)?, Value(2));
+        assert!(code.read_symbol(&mut reader).is_err());
+        Ok(())
+    }
+
     #[test]
     fn from_lengths() -> Result<()> {
         let code = HuffmanCoding::<Value>::from_lengths(&[2, 3, 4, 3, 3, 4, 2])?;
@@ -481,4 +764,200 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn dynamic_tree_code_counts() {
+        for hlit in 0..32u32 {
+            for hdist in [0u32, 31] {
+                for hclen in [0u32, 15] {
+                    let header = hlit | hdist << 5 | hclen << 10;
+                    let mut data = vec![0u8; 64];
+                    data[..2].copy_from_slice(&(header as u16).to_le_bytes());
+                    let mut reader = BitReader::new(data.as_slice());
+
+                    let Err(error) = decode_dynamic_tree(&mut reader) else {
+                        panic!("decoded an empty tree, hlit = {}", hlit);
+                    };
+                    let code_count_error = matches!(
+                        error.downcast_ref::<DecodeError>(),
+                        Some(DecodeError::InvalidHuffmanCode(_))
+                    );
+                    assert_eq!(code_count_error, hlit + 257 > 286, "hlit = {}", hlit);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn over_subscribed() {
+        let Err(error) = HuffmanCoding::<Value>::from_lengths(&[1, 1, 1]) else {
+            panic!("accepted an over-subscribed code");
+        };
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::InvalidHuffmanCode("over-subscribed code"))
+        );
+        assert!(HuffmanCoding::<Value>::from_lengths(&[1, 2, 2]).is_ok());
+        assert!(HuffmanCoding::<Value>::from_lengths(&[1, 0, 2]).is_ok());
+    }
+
+    #[test]
+    fn encoder_matches_decoder() -> Result<()> {
+        let lengths = [2, 3, 4, 3, 3, 4, 2, 0];
+        let decoder = HuffmanCoding::<Value>::from_lengths(&lengths)?;
+        let encoder = HuffmanEncoder::from_lengths(&lengths)?;
+        for symbol in 0..7 {
+            let code = encoder.encode(symbol).unwrap();
+            assert_eq!(decoder.decode_symbol(code), Some(Value(symbol)));
+        }
+        assert_eq!(encoder.encode(7), None);
+        assert_eq!(encoder.encode(100), None);
+        Ok(())
+    }
+
+    #[test]
+    fn lengths_from_frequencies() -> Result<()> {
+        // Frequencies proportional to 2^-len give back the fixed tree exactly.
+        let freqs = fixed_litlen_lengths().map(|len| 1 << (9 - len));
+        let lengths = HuffmanEncoder::lengths_from_frequencies(&freqs, 15)?;
+        assert_eq!(lengths, fixed_litlen_lengths());
+
+        // Fibonacci frequencies want a 20-bit code; the limit flattens it
+        // into a complete code of at most 7 bits.
+        let mut freqs = vec![1, 1];
+        while freqs.len() < 20 {
+            freqs.push(freqs[freqs.len() - 1] + freqs[freqs.len() - 2]);
+        }
+        let lengths = HuffmanEncoder::lengths_from_frequencies(&freqs, 7)?;
+        assert_eq!(lengths.iter().max(), Some(&7));
+        assert!(lengths.windows(2).all(|pair| pair[0] >= pair[1]));
+        let kraft: u32 = lengths.iter().map(|&len| 1 << (7 - len)).sum();
+        assert_eq!(kraft, 1 << 7);
+        let unlimited = HuffmanEncoder::lengths_from_frequencies(&freqs, 15)?;
+        assert_eq!(unlimited[..2], [15, 15]);
+        HuffmanEncoder::from_lengths(&lengths)?;
+
+        assert_eq!(
+            HuffmanEncoder::lengths_from_frequencies(&[0, 5, 0], 15)?,
+            [0, 1, 0]
+        );
+        assert_eq!(
+            HuffmanEncoder::lengths_from_frequencies(&[0, 0], 15)?,
+            [0, 0]
+        );
+        assert!(HuffmanEncoder::lengths_from_frequencies(&[1; 9], 3).is_err());
+        assert!(HuffmanEncoder::lengths_from_frequencies(&[1, 1], 16).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn length_symbols() {
+        // Symbol, extra bits and length range from the table in RFC 1951, 3.2.5.
+        let rfc = [
+            (257, 0, 3),
+            (258, 0, 4),
+            (259, 0, 5),
+            (260, 0, 6),
+            (261, 0, 7),
+            (262, 0, 8),
+            (263, 0, 9),
+            (264, 0, 10),
+            (265, 1, 11),
+            (266, 1, 13),
+            (267, 1, 15),
+            (268, 1, 17),
+            (269, 2, 19),
+            (270, 2, 23),
+            (271, 2, 27),
+            (272, 2, 31),
+            (273, 3, 35),
+            (274, 3, 43),
+            (275, 3, 51),
+            (276, 3, 59),
+            (277, 4, 67),
+            (278, 4, 83),
+            (279, 4, 99),
+            (280, 4, 115),
+            (281, 5, 131),
+            (282, 5, 163),
+            (283, 5, 195),
+            (284, 5, 227),
+            (285, 0, 258),
+        ];
+        for (symbol, extra, base) in rfc {
+            match LitLenToken::try_from(HuffmanCodeWord(symbol)) {
+                Ok(LitLenToken::Length {
+                    base: b,
+                    extra_bits,
+                }) => {
+                    assert_eq!((b, extra_bits), (base, extra), "symbol {}", symbol)
+                }
+                other => panic!("symbol {}: {:?}", symbol, other.ok()),
+            }
+        }
+        assert!(matches!(
+            LitLenToken::try_from(HuffmanCodeWord(97)),
+            Ok(LitLenToken::Literal(97))
+        ));
+        assert!(matches!(
+            LitLenToken::try_from(HuffmanCodeWord(256)),
+            Ok(LitLenToken::EndOfBlock)
+        ));
+        let error = LitLenToken::try_from(HuffmanCodeWord(286)).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::InvalidSymbol {
+                alphabet: "literal/length",
+                value: 286,
+            })
+        );
+        let error = TreeCodeToken::try_from(HuffmanCodeWord(19)).unwrap_err();
+        assert_eq!(error.to_string(), "invalid code length symbol 19");
+    }
+
+    #[test]
+    fn distance_symbols() {
+        let rfc = [
+            (0, 0, 1),
+            (1, 0, 2),
+            (2, 0, 3),
+            (3, 0, 4),
+            (4, 1, 5),
+            (5, 1, 7),
+            (6, 2, 9),
+            (7, 2, 13),
+            (8, 3, 17),
+            (9, 3, 25),
+            (10, 4, 33),
+            (11, 4, 49),
+            (12, 5, 65),
+            (13, 5, 97),
+            (14, 6, 129),
+            (15, 6, 193),
+            (16, 7, 257),
+            (17, 7, 385),
+            (18, 8, 513),
+            (19, 8, 769),
+            (20, 9, 1025),
+            (21, 9, 1537),
+            (22, 10, 2049),
+            (23, 10, 3073),
+            (24, 11, 4097),
+            (25, 11, 6145),
+            (26, 12, 8193),
+            (27, 12, 12289),
+            (28, 13, 16385),
+            (29, 13, 24577),
+        ];
+        for (symbol, extra, base) in rfc {
+            let token = DistanceToken::try_from(HuffmanCodeWord(symbol)).unwrap();
+            assert_eq!(
+                (token.base, token.extra_bits),
+                (base, extra),
+                "symbol {}",
+                symbol
+            );
+        }
+        assert!(DistanceToken::try_from(HuffmanCodeWord(30)).is_err());
+    }
 }
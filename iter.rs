@@ -0,0 +1,161 @@
+#![forbid(unsafe_code)]
+
+use std::io::BufRead;
+
+use anyhow::Result;
+
+use crate::bit_reader::BitReader;
+use crate::decoder::{finish_member, read_member_header};
+use crate::deflate::DeflateReader;
+use crate::gzip::GzipReader;
+use crate::tracking_writer::TrackingWriter;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Lazily decode a gzip stream one byte at a time.
+///
+/// Decoding only advances as far as needed to produce the next byte, so
+/// dropping the iterator early stops reading `input`. Trailing non-gzip data
+/// after the last member is ignored, as with [`decompress`](crate::decompress).
+pub fn decompress_bytes<R: BufRead>(input: R) -> DecompressBytes<R> {
+    DecompressBytes {
+        deflate: DeflateReader::new(BitReader::new(input), TrackingWriter::new(Vec::new())),
+        state: State::MemberStart,
+        members: 0,
+        position: 0,
+    }
+}
+
+/// Decode at most the first `n` bytes of output, like `head -c`. Decoding
+/// stops as soon as they are there, possibly in the middle of a block, so
+/// the footer of the member they end in is never read or checked.
+pub fn decompress_prefix<R: BufRead>(input: R, n: usize) -> Result<Vec<u8>> {
+    decompress_bytes(input).take(n).collect()
+}
+
+enum State {
+    MemberStart,
+    Body,
+    Done,
+}
+
+/// Iterator returned by [`decompress_bytes`].
+pub struct DecompressBytes<R> {
+    deflate: DeflateReader<R, TrackingWriter<Vec<u8>>>,
+    state: State,
+    members: usize,
+    position: usize,
+}
+
+impl<R: BufRead> DecompressBytes<R> {
+    /// Do the next unit of decoding work. Returns `false` at the end of the stream.
+    fn advance(&mut self) -> Result<bool> {
+        match self.state {
+            State::MemberStart => {
+                let mut gzip_reader = GzipReader::new(self.deflate.get_input());
+                let magic_consumed = self.members > 0;
+                if gzip_reader.is_empty()? || (magic_consumed && !gzip_reader.read_magic()?) {
+                    self.state = State::Done;
+                    return Ok(false);
+                }
+                read_member_header(&mut self.deflate, magic_consumed, false)?;
+                self.state = State::Body;
+            }
+            State::Body => {
+                if matches!(self.deflate.step()?, Some(block) if block.is_final) {
+                    finish_member(&mut self.deflate)?;
+                    self.members += 1;
+                    self.state = State::MemberStart;
+                }
+            }
+            State::Done => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl<R: BufRead> Iterator for DecompressBytes<R> {
+    type Item = Result<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let buffer = self.deflate.get_output();
+            if let Some(&byte) = buffer.get(self.position) {
+                self.position += 1;
+                return Some(Ok(byte));
+            }
+            buffer.clear();
+            self.position = 0;
+
+            match self.advance() {
+                Ok(true) => continue,
+                Ok(false) => return None,
+                Err(error) => {
+                    self.state = State::Done;
+                    return Some(Err(error));
+                }
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompress;
+    use crate::test_data::{FIRST, SECOND};
+
+    #[test]
+    fn matches_decompress() -> Result<()> {
+        let data = [FIRST, SECOND].concat();
+        let mut expected = Vec::new();
+        decompress(data.as_slice(), &mut expected)?;
+
+        let bytes = decompress_bytes(data.as_slice()).collect::<Result<Vec<_>>>()?;
+        assert_eq!(bytes, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn stops_early() -> Result<()> {
+        let mut input = FIRST;
+        let newline = decompress_bytes(&mut input)
+            .position(|byte| matches!(byte, Ok(b'\n')))
+            .unwrap();
+        assert_eq!(newline, "line 0 of the first member".len());
+        assert!(!input.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn prefix() -> Result<()> {
+        let data = [FIRST, SECOND].concat();
+        let mut expected = Vec::new();
+        decompress(data.as_slice(), &mut expected)?;
+
+        assert_eq!(decompress_prefix(data.as_slice(), 10)?, &expected[..10]);
+        assert_eq!(decompress_prefix(data.as_slice(), 0)?, b"");
+        assert_eq!(decompress_prefix(data.as_slice(), usize::MAX)?, expected);
+
+        // The damaged footer is never reached.
+        let mut data = FIRST.to_vec();
+        let crc = data.len() - 8;
+        data[crc] ^= 0xff;
+        assert_eq!(decompress_prefix(data.as_slice(), 10)?, &expected[..10]);
+        assert!(decompress_prefix(data.as_slice(), usize::MAX).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn reports_error_once() {
+        let mut data = FIRST.to_vec();
+        let crc = data.len() - 8;
+        data[crc] ^= 0xff;
+
+        let mut bytes = decompress_bytes(data.as_slice());
+        assert!(bytes.by_ref().any(|byte| byte.is_err()));
+        assert!(bytes.next().is_none());
+    }
+}
@@ -2,50 +2,1177 @@
 
 use std::{
     // error,
-    io::{BufRead, Write},
+    fs::File,
+    io::{self, BufRead, BufWriter, Seek, Write},
 };
 
-use anyhow::Result;
-use tracking_writer::TrackingWriter;
-
-use crate::gzip::GzipReader;
-use bit_reader::BitReader;
-use deflate::DeflateReader;
+use anyhow::{bail, Context, Result};
+use byteorder::{LittleEndian, ReadBytesExt};
 
+#[cfg(feature = "bgzf")]
+mod bgzf;
 mod bit_reader;
+mod bit_writer;
+mod checksum;
+mod chunked;
+mod compress;
+mod decoder;
 mod deflate;
+mod detect;
+mod error;
+mod fuzz;
 mod gzip;
 mod huffman_coding;
+mod iter;
+mod options;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod report;
+mod sink;
+mod state;
+mod tee;
+#[cfg(test)]
+mod test_data;
+mod tracking_reader;
 mod tracking_writer;
+mod zlib;
+
+#[cfg(feature = "bgzf")]
+pub use bgzf::{has_bgzf_eof_marker, Bgzf};
+pub use bit_reader::{BitReader, BitSequence};
+pub use bit_writer::BitWriter;
+pub use checksum::{Adler32, Checksum, Crc32, NoChecksum};
+pub use chunked::decompress_chunked;
+pub use compress::{compress_fixed, compress_stored};
+pub use decoder::Decoder;
+pub use deflate::{
+    inflate, inflate_blocks, inflate_reader, inflate_to_sink, BlockHeader, BlockInfo,
+    CompressionType, DeflateReader, InflateBlocks, InflateReader,
+};
+pub use detect::{decompress_auto, decompress_auto_with, Format};
+pub use error::{DecodeError, DecodeOffset};
+pub use fuzz::fuzz_decompress;
+pub use gzip::{
+    CompressionHint, CompressionMethod, ExtraSubfield, FooterCheck, GzipReader, MemberFlags,
+    MemberFooter, MemberHeader,
+};
+pub use huffman_coding::HuffmanEncoder;
+pub use iter::{decompress_bytes, decompress_prefix, DecompressBytes};
+pub use options::{
+    DecodeOptions, DecodeOptionsBuilder, FlushPolicy, HeaderProfile, UnknownOsPolicy,
+};
+#[cfg(feature = "rayon")]
+pub use parallel::decompress_parallel;
+pub use report::{DecodeStats, Decoded, MemberFailure, Warning};
+pub use sink::OutputSink;
+pub use state::DecoderState;
+pub use tee::TeeWriter;
+pub use tracking_reader::TrackingReader;
+pub use tracking_writer::TrackingWriter;
+pub use zlib::{decompress_zlib, decompress_zlib_with_dictionary, ZlibHeader};
+
+/// Decode a gzip stream, returning the number of members it contained.
+pub fn decompress<R: BufRead, W: Write>(input: R, output: W) -> Result<usize> {
+    Ok(decompress_with(DecodeOptions::default(), input, output)?.members)
+}
+
+/// Append the decoded bytes to `out`. Clearing it between calls keeps its
+/// capacity, so one buffer can be reused for many small streams.
+pub fn decompress_into<R: BufRead>(input: R, out: &mut Vec<u8>) -> Result<()> {
+    decompress(input, out)?;
+    Ok(())
+}
+
+/// Decode into a [`bytes::Bytes`], which can be handed on without copying.
+#[cfg(feature = "bytes")]
+pub fn decompress_to_bytes<R: BufRead>(input: R) -> Result<bytes::Bytes> {
+    use bytes::BufMut;
+
+    let mut output = bytes::BytesMut::new().writer();
+    decompress(input, &mut output)?;
+    Ok(output.into_inner().freeze())
+}
 
-pub fn decompress<R: BufRead, W: Write>(input: R, output: W) -> Result<()> {
-    let mut deflate = DeflateReader::new(BitReader::new(input), TrackingWriter::new(output));
-    let mut gzip_reader = GzipReader::new(deflate.get_input());
-    while !gzip_reader.is_empty()? {
-        match gzip_reader.parse_header() {
-            Ok(()) => loop {
-                match deflate.next_block() {
-                    Ok(x) => {
-                        if x {
-                            break;
-                        } else {
-                            continue;
-                        }
+/// Decode into `file` at its current position, sizing it for the expected
+/// `len` (e.g. from ISIZE) up front so the file system can allocate it in
+/// one go. The file is written through a `BufWriter`, not memory-mapped:
+/// mapping takes `unsafe`, which this crate forbids. If decoding fails or
+/// the decoded size turns out different, the file is cut back to what was
+/// decoded and an error returned.
+pub fn decompress_to_file<R: BufRead>(input: R, file: &File, len: u64) -> Result<()> {
+    let mut output = BufWriter::new(file);
+    let start = output.stream_position()?;
+    file.set_len(start + len)?;
+    let result = decompress(input, &mut output);
+    // Seeking flushes whatever the BufWriter still holds.
+    let written = output.stream_position()? - start;
+    if written != len {
+        file.set_len(start + written)?;
+    }
+    result?;
+    if written != len {
+        bail!("decoded {} bytes, expected {}", written, len);
+    }
+    Ok(())
+}
+
+/// Decode only the first member and return its header. Nothing past the
+/// member's footer is read, so `input` can be handed on for whatever follows.
+pub fn decompress_one<R: BufRead, W: Write>(input: R, output: W) -> Result<MemberHeader> {
+    match Decoder::new(input, output).decode_one()? {
+        Some(header) => Ok(header),
+        None => Err(anyhow::Error::new(DecodeError::UnexpectedEof).context("no gzip member")),
+    }
+}
+
+/// Seek `input` to `offset` and decode the single member found there, like
+/// [`decompress_one`]. `input` is left just past the member's footer, e.g.
+/// to walk a container that stores gzip members at known offsets.
+pub fn decompress_from_offset<R: BufRead + Seek, W: Write>(
+    mut input: R,
+    offset: u64,
+    output: W,
+) -> Result<MemberHeader> {
+    input.seek(io::SeekFrom::Start(offset))?;
+    decompress_one(&mut input, output)
+}
+
+/// Decode every member into a writer of its own, e.g. to explode a
+/// concatenated archive into one file per member named after FNAME.
+/// `writer_for` gets each member's header and index before its body is
+/// decoded. Every member is still checked against its own footer. Returns
+/// the number of members.
+pub fn decompress_members_to<R: BufRead>(
+    input: R,
+    mut writer_for: impl FnMut(&MemberHeader, usize) -> Result<Box<dyn Write>>,
+) -> Result<usize> {
+    let mut decoder = Decoder::new(input, Box::new(io::sink()) as Box<dyn Write>);
+    loop {
+        let index = decoder.decoded().members;
+        // The previous member's writer was flushed when it was finished.
+        let next = decoder.decode_one_with(|header, output| {
+            *output = writer_for(header, index)?;
+            Ok(())
+        })?;
+        if next.is_none() {
+            return Ok(index);
+        }
+    }
+}
+
+/// CRC32 of the whole decompressed stream, across all members. The data
+/// itself is discarded as it is decoded.
+pub fn compute_crc32<R: BufRead>(input: R) -> Result<u32> {
+    let mut output = TrackingWriter::new(io::sink());
+    decompress(input, &mut output)?;
+    Ok(output.crc32())
+}
+
+/// Decode like [`decompress`], but collect every member header and report
+/// suspicious but harmless things as [`Warning`]s: reserved FLG bits, an
+/// unknown OS, an MTIME in the future, a non-UTF-8 FNAME or FCOMMENT.
+pub fn decompress_verbose<R: BufRead, W: Write>(input: R, output: W) -> Result<Decoded> {
+    let options = DecodeOptions::builder()
+        .lossy_text(true)
+        .unknown_os(UnknownOsPolicy::Warn)
+        .build();
+    decompress_with(options, input, output)
+}
+
+/// `(ISIZE, CRC32)` from the footer of every member, e.g. to build a
+/// manifest of a multi-member archive. DEFLATE bodies don't record their
+/// length, so each body is still decoded and checked against its footer;
+/// only the output is thrown away. Trailing non-gzip data is ignored, as
+/// with [`decompress`].
+pub fn member_sizes<R: BufRead>(input: R) -> Result<Vec<(u32, u32)>> {
+    let mut decoder = Decoder::new(input, io::sink());
+    let decoded = decoder.decode_all()?;
+    Ok(decoded
+        .footers
+        .iter()
+        .map(|footer| (footer.data_size, footer.data_crc32))
+        .collect())
+}
+
+/// Expected decoded size, from the ISIZE in the last four bytes of `input`,
+/// without decoding anything. The stream position is left where it was.
+///
+/// ISIZE only covers the last member, modulo 2^32, so this is the size of
+/// the whole output only for a single member under 4 GiB. Summing the
+/// members of a multi-member file means decoding it, see [`member_sizes`].
+pub fn estimated_output_size<R: BufRead + Seek>(mut input: R) -> Result<u64> {
+    let position = input.stream_position()?;
+    input
+        .seek(io::SeekFrom::End(-4))
+        .context("input too short for ISIZE")?;
+    let isize = input.read_u32::<LittleEndian>().context("ISIZE")?;
+    input.seek(io::SeekFrom::Start(position))?;
+    Ok(u64::from(isize))
+}
+
+/// Decode like [`decompress`] and return the footer of every member. Each
+/// footer has already been checked against the decoded data; a mismatch fails
+/// with [`DecodeError::DataCrcMismatch`] or [`DecodeError::LengthMismatch`],
+/// which carry both the declared and the computed value.
+pub fn decompress_all_to_writer<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+) -> Result<Vec<MemberFooter>> {
+    let mut decoder = Decoder::new(input, output);
+    decoder.decode_all()?;
+    Ok(decoder.into_decoded().footers)
+}
+
+/// Decode like [`decompress`] and summarize how the stream was compressed:
+/// sizes, the mix of block types and how much output the blocks hold.
+pub fn decompress_with_stats<R: BufRead, W: Write>(input: R, output: W) -> Result<DecodeStats> {
+    let mut decoder = Decoder::new(input, output);
+    decoder.decode_all()?;
+    Ok(decoder.stats().clone())
+}
+
+pub fn decompress_with<R: BufRead, W: Write>(
+    options: DecodeOptions,
+    input: R,
+    output: W,
+) -> Result<Decoded> {
+    let mut decoder = Decoder::with_options(options, input, output);
+    decoder.decode_all()?;
+    Ok(decoder.into_decoded())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::{ChunkedReader, EMPTY, FIRST, RAW, SECOND, STORED, ZLIB};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn corrupt_crc(member: &[u8]) -> Vec<u8> {
+        let mut member = member.to_vec();
+        let crc = member.len() - 8;
+        member[crc] ^= 0xff;
+        member
+    }
+
+    #[test]
+    fn footer_mismatch() {
+        let mut data = FIRST.to_vec();
+        let crc = data.len() - 8;
+        data[crc] ^= 0xff;
+        let error = decompress(data.as_slice(), Vec::new()).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::DataCrcMismatch {
+                expected: 0x7841e941,
+                actual: 0x7841e9be,
+            })
+        );
+
+        let mut data = FIRST.to_vec();
+        let isize = data.len() - 4;
+        data[isize] += 1;
+        let error = decompress(data.as_slice(), Vec::new()).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::LengthMismatch {
+                expected: 551,
+                actual: 550,
+            })
+        );
+    }
+
+    #[test]
+    fn strict_by_default() {
+        let data = [FIRST, &corrupt_crc(SECOND), FIRST].concat();
+        assert!(decompress(data.as_slice(), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn lenient_skips_bad_member() -> Result<()> {
+        let data = [FIRST, &corrupt_crc(SECOND), &[0, 1, 2], FIRST].concat();
+        let options = DecodeOptions::builder().lenient(true).build();
+        let decoded = decompress_with(options, data.as_slice(), Vec::new())?;
+        assert_eq!(decoded.failed_members.len(), 1);
+        assert_eq!(decoded.failed_members[0].index, 1);
+        assert_eq!(decoded.members, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn lenient_resyncs_after_garbage() -> Result<()> {
+        let data = [FIRST, b"garbage\x1f", FIRST].concat();
+        let mut output = Vec::new();
+        let options = DecodeOptions::builder().lenient(true).build();
+        let decoded = decompress_with(options, data.as_slice(), &mut output)?;
+
+        let mut expected = Vec::new();
+        decompress([FIRST, FIRST].concat().as_slice(), &mut expected)?;
+        assert_eq!(output, expected);
+        assert_eq!(decoded.failed_members.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn trailing_garbage() -> Result<()> {
+        let data = [FIRST, b"\0\0\0\0"].concat();
+        let mut output = Vec::new();
+        let decoded = decompress_with(DecodeOptions::default(), data.as_slice(), &mut output)?;
+        assert_eq!(decoded.input_consumed, FIRST.len() as u64);
+        let mut expected = Vec::new();
+        decompress(FIRST, &mut expected)?;
+        assert_eq!(output, expected);
+
+        let options = DecodeOptions::builder().strict(true).build();
+        let error = decompress_with(options, data.as_slice(), Vec::new()).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::TrailingGarbage {
+                offset: FIRST.len() as u64
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn trailing_garbage_split_across_reads() -> Result<()> {
+        for garbage in [&b"\x1f\0\0\0"[..], b"\x1f"] {
+            let data = [FIRST, garbage].concat();
+            for strict in [false, true] {
+                let options = DecodeOptions::builder().strict(strict).build();
+                let whole = decompress_with(options.clone(), data.as_slice(), Vec::new());
+                let split = io::BufReader::with_capacity(1, data.as_slice());
+                let split = decompress_with(options, split, Vec::new());
+                match (whole, split) {
+                    (Ok(whole), Ok(split)) => {
+                        assert!(!strict);
+                        assert_eq!(whole.members, 1);
+                        assert_eq!(whole.input_consumed, FIRST.len() as u64);
+                        assert_eq!(split.input_consumed, whole.input_consumed);
                     }
-                    Err(error) => {
-                        return Err(error);
+                    (Err(whole), Err(split)) => {
+                        assert!(strict);
+                        let expected = DecodeError::TrailingGarbage {
+                            offset: FIRST.len() as u64,
+                        };
+                        assert_eq!(whole.downcast_ref(), Some(&expected));
+                        assert_eq!(split.downcast_ref(), Some(&expected));
                     }
+                    (whole, split) => panic!("{:?} vs {:?}", whole, split),
                 }
-            },
-            Err(error) => {
-                return Err(error);
             }
         }
-        gzip_reader = GzipReader::new(deflate.get_input());
-        let (crc32, isize) = gzip_reader.read_crc32_and_isize()?;
-        deflate.check_crc32_and_isize(crc32, isize)?;
-        deflate.output()?;
-        gzip_reader = GzipReader::new(deflate.get_input());
+        Ok(())
+    }
+
+    #[test]
+    fn garbage_instead_of_first_member() {
+        assert!(decompress(b"not gzip".as_slice(), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn ignore_checksum() -> Result<()> {
+        let data = [FIRST, &corrupt_crc(SECOND)].concat();
+        let mut expected = Vec::new();
+        decompress([FIRST, SECOND].concat().as_slice(), &mut expected)?;
+
+        let mut output = Vec::new();
+        let options = DecodeOptions::builder().ignore_checksum(true).build();
+        let decoded = decompress_with(options, data.as_slice(), &mut output)?;
+        assert_eq!(output, expected);
+        assert!(decoded.failed_members.is_empty());
+        assert!(matches!(
+            decoded.warnings.as_slice(),
+            [Warning::FooterMismatch {
+                member: 1,
+                error: DecodeError::DataCrcMismatch { .. },
+            }]
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn ignore_checksum_flushes_output() -> Result<()> {
+        // Bytes only reach `delivered` when flushed, like a BufWriter's sink.
+        #[derive(Default)]
+        struct Delivery {
+            pending: Vec<u8>,
+            delivered: Vec<u8>,
+        }
+
+        impl Write for Delivery {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.pending.write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                self.delivered.append(&mut self.pending);
+                Ok(())
+            }
+        }
+
+        let mut expected = Vec::new();
+        decompress(FIRST, &mut expected)?;
+
+        let data = corrupt_crc(FIRST);
+        for policy in [
+            FlushPolicy::EveryBlock,
+            FlushPolicy::EveryNBytes(100),
+            FlushPolicy::AtEnd,
+        ] {
+            let options = DecodeOptions::builder()
+                .ignore_checksum(true)
+                .flush_every(policy)
+                .build();
+            let mut output = Delivery::default();
+            let decoded = decompress_with(options, data.as_slice(), &mut output)?;
+            assert_eq!(output.delivered, expected);
+            assert!(output.pending.is_empty());
+            assert_eq!(decoded.members, 1);
+            assert!(matches!(
+                decoded.warnings.as_slice(),
+                [Warning::FooterMismatch {
+                    member: 0,
+                    error: DecodeError::DataCrcMismatch { .. },
+                }]
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn max_output() -> Result<()> {
+        let options = DecodeOptions::builder().max_output(550).build();
+        decompress_with(options, FIRST, Vec::new())?;
+
+        let options = DecodeOptions::builder().max_output(549).build();
+        let mut output = Vec::new();
+        let error = decompress_with(options, FIRST, &mut output).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::OutputLimitExceeded { limit: 549 })
+        );
+        assert!(output.len() <= 549);
+        Ok(())
+    }
+
+    #[test]
+    fn max_members() -> Result<()> {
+        let data = [EMPTY, FIRST, EMPTY].concat();
+        let options = DecodeOptions::builder().max_members(3).build();
+        assert_eq!(
+            decompress_with(options, data.as_slice(), Vec::new())?.members,
+            3
+        );
+
+        let options = DecodeOptions::builder().max_members(2).build();
+        let error = decompress_with(options, data.as_slice(), Vec::new()).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::TooManyMembers { limit: 2 })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nonstandard_header() -> Result<()> {
+        let mut data = FIRST.to_vec();
+        data[3] |= 0x40;
+        data[9] = 200;
+        let mut expected = Vec::new();
+        decompress(FIRST, &mut expected)?;
+
+        let mut output = Vec::new();
+        let decoded = decompress_with(DecodeOptions::default(), data.as_slice(), &mut output)?;
+        assert_eq!(output, expected);
+        assert_eq!(
+            decoded.warnings,
+            vec![Warning::ReservedFlags {
+                member: 0,
+                flags: 0x40
+            },]
+        );
+
+        let options = DecodeOptions::builder().strict(true).build();
+        let error = decompress_with(options, data.as_slice(), Vec::new()).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::ReservedFlags { flags: 0x40 })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_os_policy() -> Result<()> {
+        let mut data = FIRST.to_vec();
+        data[9] = 200;
+        let decode = |builder: DecodeOptionsBuilder| {
+            decompress_with(builder.build(), data.as_slice(), io::sink())
+        };
+        // Ignored unless asked for, strict or not.
+        assert!(decode(DecodeOptions::builder())?.warnings.is_empty());
+        assert!(decode(DecodeOptions::builder().strict(true))?
+            .warnings
+            .is_empty());
+        let ignore = DecodeOptions::builder().unknown_os(UnknownOsPolicy::Ignore);
+        assert!(decode(ignore)?.warnings.is_empty());
+
+        let reject = DecodeOptions::builder().unknown_os(UnknownOsPolicy::Reject);
+        let error = decode(reject).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::UnknownOs { os: 200 })
+        );
+
+        let warn = DecodeOptions::builder()
+            .strict(true)
+            .unknown_os(UnknownOsPolicy::Warn);
+        assert_eq!(
+            decode(warn)?.warnings,
+            vec![Warning::UnknownOs { member: 0, os: 200 }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn expect_header() -> Result<()> {
+        let profile = HeaderProfile {
+            os: Some(3),
+            modification_time: Some(0),
+            extra_flags: Some(2),
+        };
+        let options = DecodeOptions::builder().expect_header(profile).build();
+        let data = [FIRST, SECOND].concat();
+        assert_eq!(
+            decompress_with(options.clone(), data.as_slice(), io::sink())?.members,
+            2
+        );
+
+        let mut odd = SECOND.to_vec();
+        odd[4] = 1;
+        let data = [FIRST, &odd].concat();
+        let error = decompress_with(options, data.as_slice(), io::sink()).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::HeaderMismatch {
+                field: "MTIME",
+                expected: 0,
+                actual: 1,
+            })
+        );
+        assert!(format!("{:#}", error).starts_with("gzip member 1"));
+
+        // Unset fields are not checked.
+        let os_only = HeaderProfile {
+            os: Some(3),
+            ..HeaderProfile::default()
+        };
+        let options = DecodeOptions::builder().expect_header(os_only).build();
+        decompress_with(options, data.as_slice(), io::sink())?;
+        Ok(())
+    }
+
+    #[test]
+    fn member_count() -> Result<()> {
+        assert_eq!(decompress(FIRST, Vec::new())?, 1);
+        let data = [FIRST, SECOND, FIRST].concat();
+        assert_eq!(decompress(data.as_slice(), Vec::new())?, 3);
+        assert_eq!(decompress(&[][..], Vec::new())?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn empty_member() -> Result<()> {
+        let mut output = Vec::new();
+        assert_eq!(decompress(EMPTY, &mut output)?, 1);
+        assert!(output.is_empty());
+        assert_eq!(super::compute_crc32(EMPTY)?, 0);
+
+        // The same, as gzip itself writes it: an empty fixed-Huffman block.
+        let fixed = [
+            31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        assert_eq!(decompress(&fixed[..], &mut output)?, 1);
+        assert!(output.is_empty());
+
+        let mut expected = Vec::new();
+        decompress(FIRST, &mut expected)?;
+        let data = [EMPTY, FIRST, EMPTY, &fixed, EMPTY].concat();
+        assert_eq!(decompress(data.as_slice(), &mut output)?, 5);
+        assert_eq!(output, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_utf8_name() {
+        let mut bad = vec![0x1f, 0x8b, 8, 0x08, 0, 0, 0, 0, 0, 3, 0xff, 0xfe, 0];
+        bad.extend(&EMPTY[10..]);
+        let data = [FIRST, &bad].concat();
+        let error = decompress(data.as_slice(), Vec::new()).unwrap_err();
+        let end = FIRST.len() + 13;
+        let prefix = format!(
+            "gzip member 1: at bit {} (byte {}): invalid UTF-8 in FNAME: ",
+            end * 8,
+            end
+        );
+        let message = format!("{:#}", error);
+        assert!(message.starts_with(&prefix), "{}", message);
+    }
+
+    #[test]
+    fn decompress_verbose() -> Result<()> {
+        let mut odd = vec![
+            0x1f, 0x8b, 8, 0x48, 0xff, 0xff, 0xff, 0xff, 0, 3, b'a', 0xff, 0,
+        ];
+        odd.extend(&EMPTY[10..]);
+        let data = [FIRST, &odd].concat();
+        let mut expected = Vec::new();
+        decompress(FIRST, &mut expected)?;
+
+        let mut output = Vec::new();
+        let decoded = super::decompress_verbose(data.as_slice(), &mut output)?;
+        assert_eq!(output, expected);
+        assert_eq!(decoded.headers.len(), 2);
+        assert_eq!(decoded.headers[1].name.as_deref(), Some("a\u{fffd}"));
+        assert!(decoded.headers[1].lossy_text);
+        assert_eq!(
+            decoded.warnings,
+            vec![
+                Warning::ReservedFlags {
+                    member: 1,
+                    flags: 0x40
+                },
+                Warning::FutureMtime {
+                    member: 1,
+                    mtime: u32::MAX
+                },
+                Warning::LossyText { member: 1 },
+            ]
+        );
+        Ok(())
+    }
+
+    /// `member` (which has a bare 10-byte header) with the header rebuilt from
+    /// the given FLG, optional fields and OS. An FHCRC is computed if asked for.
+    fn with_header(
+        member: &[u8],
+        flags: u8,
+        extra: Option<&[u8]>,
+        name: Option<&str>,
+        comment: Option<&str>,
+        os: u8,
+    ) -> Vec<u8> {
+        let mut data = vec![0x1f, 0x8b, 8, flags, 0x78, 0x56, 0x34, 0x12, 0, os];
+        if let Some(extra) = extra {
+            data.extend((extra.len() as u16).to_le_bytes());
+            data.extend(extra);
+        }
+        for text in [name, comment].into_iter().flatten() {
+            data.extend(text.as_bytes());
+            data.push(0);
+        }
+        if flags & 0x02 != 0 {
+            let crc16 = checksum::ALGORITHM.checksum(&data) as u16;
+            data.extend(crc16.to_le_bytes());
+        }
+        data.extend(&member[10..]);
+        data
+    }
+
+    #[test]
+    fn members_with_varied_headers() -> Result<()> {
+        let extra = [b'A', b'p', 2, 0, 7, 8];
+        let members = [
+            // FNAME | FCOMMENT
+            with_header(FIRST, 0x18, None, Some("first.txt"), Some("hi"), 3),
+            // FEXTRA | FHCRC
+            with_header(SECOND, 0x06, Some(&extra), None, None, 11),
+            // Every optional field at once, plus FTEXT.
+            with_header(FIRST, 0x1f, Some(&extra), Some("all"), Some("of it"), 0),
+            with_header(EMPTY, 0x00, None, None, None, 255),
+            // FHCRC over nothing but the fixed fields.
+            with_header(SECOND, 0x02, None, None, None, 3),
+        ];
+        let data = members.concat();
+        let mut expected = Vec::new();
+        for member in &members {
+            decompress(member.as_slice(), &mut expected)?;
+        }
+
+        let mut output = Vec::new();
+        let decoded = super::decompress_verbose(data.as_slice(), &mut output)?;
+        assert_eq!(output, expected);
+        assert!(decoded.warnings.is_empty());
+        let mut chunked = Vec::new();
+        let decoded_chunked = super::decompress_verbose(ChunkedReader::new(&data), &mut chunked)?;
+        assert_eq!(chunked, expected);
+
+        for headers in [&decoded.headers, &decoded_chunked.headers] {
+            let fields = headers
+                .iter()
+                .map(|header| {
+                    (
+                        header.extra.as_deref(),
+                        header.name.as_deref(),
+                        header.comment.as_deref(),
+                        header.has_crc,
+                        header.is_text,
+                        header.os,
+                        header.modification_time,
+                    )
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(
+                fields,
+                vec![
+                    (
+                        None,
+                        Some("first.txt"),
+                        Some("hi"),
+                        false,
+                        false,
+                        3,
+                        0x12345678
+                    ),
+                    (Some(&extra[..]), None, None, true, false, 11, 0x12345678),
+                    (
+                        Some(&extra[..]),
+                        Some("all"),
+                        Some("of it"),
+                        true,
+                        true,
+                        0,
+                        0x12345678
+                    ),
+                    (None, None, None, false, false, 255, 0x12345678),
+                    (None, None, None, true, false, 3, 0x12345678),
+                ]
+            );
+        }
+        assert_eq!(decoded.headers[2].extra_subfields()?[0].data, [7, 8]);
+        Ok(())
+    }
+
+    /// A writer the test can still look at after handing it out.
+    #[derive(Clone, Default)]
+    struct Shared(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn decompress_members_to() -> Result<()> {
+        let first = with_header(FIRST, 0x08, None, Some("first.txt"), None, 3);
+        let data = [first.as_slice(), SECOND, EMPTY].concat();
+        let mut outputs: Vec<(Option<String>, Shared)> = vec![];
+        let members = super::decompress_members_to(data.as_slice(), |header, index| {
+            assert_eq!(index, outputs.len());
+            let output = Shared::default();
+            outputs.push((header.name.clone(), output.clone()));
+            Ok(Box::new(output))
+        })?;
+        assert_eq!(members, 3);
+
+        for ((name, output), member) in outputs.iter().zip([FIRST, SECOND, EMPTY]) {
+            let mut expected = Vec::new();
+            decompress(member, &mut expected)?;
+            assert_eq!(*output.0.borrow(), expected);
+            assert_eq!(name.is_some(), member == FIRST);
+        }
+        assert_eq!(outputs[0].0.as_deref(), Some("first.txt"));
+
+        let data = [FIRST, &corrupt_crc(SECOND)].concat();
+        let error = super::decompress_members_to(data.as_slice(), |_, _| Ok(Box::new(io::sink())))
+            .unwrap_err();
+        assert_eq!(error.to_string(), "gzip member 1");
+        Ok(())
+    }
+
+    #[test]
+    fn header_without_data() {
+        for error in [
+            decompress(&FIRST[..10], io::sink()).unwrap_err(),
+            super::decompress_one(&FIRST[..10], io::sink()).unwrap_err(),
+        ] {
+            assert_eq!(
+                error.downcast_ref::<DecodeError>(),
+                Some(&DecodeError::UnexpectedEof)
+            );
+            assert!(format!("{:#}", error).contains("no DEFLATE data after header"));
+        }
+        let error = decompress(&FIRST[..11], io::sink()).unwrap_err();
+        assert!(!format!("{:#}", error).contains("no DEFLATE data after header"));
+    }
+
+    #[test]
+    fn decompress_from_offset() -> Result<()> {
+        let data = [b"custom header".as_slice(), FIRST, SECOND].concat();
+        let mut expected = Vec::new();
+        decompress(SECOND, &mut expected)?;
+
+        let mut input = io::BufReader::with_capacity(7, io::Cursor::new(&data));
+        let mut output = Vec::new();
+        let second = (13 + FIRST.len()) as u64;
+        super::decompress_from_offset(&mut input, second, &mut output)?;
+        assert_eq!(output, expected);
+        assert_eq!(input.stream_position()?, data.len() as u64);
+
+        super::decompress_from_offset(&mut input, 13, io::sink())?;
+        assert_eq!(input.stream_position()?, second);
+        assert!(super::decompress_from_offset(&mut input, 0, io::sink()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn estimated_output_size() -> Result<()> {
+        let mut expected = Vec::new();
+        decompress(FIRST, &mut expected)?;
+        let mut input = io::Cursor::new(FIRST);
+        input.set_position(3);
+        assert_eq!(
+            super::estimated_output_size(&mut input)?,
+            expected.len() as u64
+        );
+        assert_eq!(input.position(), 3);
+
+        // Only the last member counts.
+        let data = [SECOND, FIRST].concat();
+        let size = super::estimated_output_size(io::Cursor::new(&data))?;
+        assert_eq!(size, expected.len() as u64);
+        assert!(super::estimated_output_size(io::Cursor::new(&FIRST[..3])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn member_sizes() -> Result<()> {
+        let data = [FIRST, EMPTY, SECOND, b"junk"].concat();
+        let sizes = super::member_sizes(data.as_slice())?;
+        let mut first = Vec::new();
+        decompress(FIRST, &mut first)?;
+        assert_eq!(sizes.len(), 3);
+        assert_eq!(sizes[0], (first.len() as u32, super::compute_crc32(FIRST)?));
+        assert_eq!(sizes[1], (0, 0));
+        assert!(super::member_sizes(&[][..])?.is_empty());
+        Ok(())
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn decompress_to_bytes() -> Result<()> {
+        let data = [FIRST, SECOND].concat();
+        let mut expected = Vec::new();
+        decompress(data.as_slice(), &mut expected)?;
+        assert_eq!(super::decompress_to_bytes(data.as_slice())?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn flush_policy() -> Result<()> {
+        struct CountFlushes(usize);
+
+        impl Write for CountFlushes {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                self.0 += 1;
+                Ok(())
+            }
+        }
+
+        let data = [FIRST, SECOND, FIRST].concat();
+        let flushes = |policy| -> Result<usize> {
+            let options = DecodeOptions::builder().flush_every(policy).build();
+            let mut output = CountFlushes(0);
+            decompress_with(options, data.as_slice(), &mut output)?;
+            Ok(output.0)
+        };
+        assert_eq!(flushes(FlushPolicy::AtEnd)?, 1);
+        // Member ends, then the end of the stream.
+        assert_eq!(flushes(FlushPolicy::EveryNBytes(usize::MAX))?, 4);
+        // Every member is a single block.
+        assert_eq!(flushes(FlushPolicy::EveryBlock)?, 3 + 4);
+        Ok(())
+    }
+
+    #[test]
+    fn decompress_with_stats() -> Result<()> {
+        let data = [FIRST, STORED, b"junk"].concat();
+        let mut output = Vec::new();
+        let stats = super::decompress_with_stats(data.as_slice(), &mut output)?;
+        let first = inflate_blocks(&FIRST[10..]).next().unwrap()?.decoded_bytes;
+        let stored = output.len() as u64 - first;
+        assert_eq!(
+            stats,
+            DecodeStats {
+                members: 2,
+                input_bytes: (FIRST.len() + STORED.len()) as u64,
+                output_bytes: output.len() as u64,
+                stored_blocks: 1,
+                fixed_blocks: 0,
+                dynamic_blocks: 1,
+                min_block_output: first.min(stored),
+                max_block_output: first.max(stored),
+            }
+        );
+        assert_eq!(stats.mean_block_output(), output.len() as f64 / 2.0);
+        assert!(stats.compression_ratio() > Some(0.0));
+
+        let stats = super::decompress_with_stats(&b""[..], Vec::new())?;
+        assert_eq!(stats, DecodeStats::default());
+        assert_eq!(stats.compression_ratio(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn decompress_all_to_writer() -> Result<()> {
+        let data = [FIRST, EMPTY, b"junk"].concat();
+        let mut output = Vec::new();
+        let footers = super::decompress_all_to_writer(data.as_slice(), &mut output)?;
+        assert_eq!(
+            footers,
+            vec![
+                MemberFooter {
+                    data_crc32: super::compute_crc32(FIRST)?,
+                    data_size: output.len() as u32
+                },
+                MemberFooter {
+                    data_crc32: 0,
+                    data_size: 0
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn back_reference_into_previous_member() -> Result<()> {
+        // A member whose fixed block starts with a length 3, distance 1 match.
+        let mut writer = BitWriter::new(EMPTY[..10].to_vec());
+        writer.write_bits(BitSequence::new(0b011, 3))?;
+        writer.write_bits(BitSequence::from_msb(1, 7))?;
+        writer.write_bits(BitSequence::from_msb(0, 5))?;
+        writer.write_bits(BitSequence::from_msb(0, 7))?;
+        let mut member = writer.borrow_writer_from_boundary()?.clone();
+        member.extend(&EMPTY[15..]);
+
+        let data = [FIRST, &member].concat();
+        let error = decompress(data.as_slice(), Vec::new()).unwrap_err();
+        let expected = DecodeError::DistanceTooFar {
+            distance: 1,
+            available: 0,
+        };
+        assert_eq!(error.downcast_ref::<DecodeError>(), Some(&expected));
+        assert_eq!(
+            expected.to_string(),
+            "back-reference distance 1 before any output of the member"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn future_mtime() -> Result<()> {
+        let mut data = FIRST.to_vec();
+        data[4..8].copy_from_slice(&1_000_000u32.to_le_bytes());
+        let now = UNIX_EPOCH + Duration::from_secs(999_000);
+        let decode = |threshold| {
+            let options = DecodeOptions::builder()
+                .now(now)
+                .future_mtime_threshold(Duration::from_secs(threshold))
+                .build();
+            decompress_with(options, data.as_slice(), Vec::new())
+        };
+
+        assert!(decode(1000)?.warnings.is_empty());
+        assert_eq!(
+            decode(999)?.warnings,
+            vec![Warning::FutureMtime {
+                member: 0,
+                mtime: 1_000_000
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn decompress_into() -> Result<()> {
+        let mut expected = Vec::new();
+        decompress([FIRST, SECOND].concat().as_slice(), &mut expected)?;
+
+        let mut out = Vec::new();
+        super::decompress_into(FIRST, &mut out)?;
+        super::decompress_into(SECOND, &mut out)?;
+        assert_eq!(out, expected);
+
+        let capacity = out.capacity();
+        out.clear();
+        super::decompress_into(FIRST, &mut out)?;
+        assert!(expected.starts_with(&out));
+        assert_eq!(out.capacity(), capacity);
+        Ok(())
+    }
+
+    #[test]
+    fn compute_crc32() -> Result<()> {
+        assert_eq!(super::compute_crc32(FIRST)?, 0x7841e9be);
+
+        let data = [FIRST, SECOND].concat();
+        let mut output = Vec::new();
+        decompress(data.as_slice(), &mut output)?;
+        assert_eq!(
+            super::compute_crc32(data.as_slice())?,
+            checksum::ALGORITHM.checksum(&output)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn other_crc_algorithm() -> Result<()> {
+        static BZIP2: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_BZIP2);
+        let mut output = Vec::new();
+        decompress(FIRST, &mut output)?;
+        let mut data = FIRST.to_vec();
+        let footer = data.len() - 8;
+        data[footer..footer + 4].copy_from_slice(&BZIP2.checksum(&output).to_le_bytes());
+
+        let error = decompress(data.as_slice(), Vec::new()).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<DecodeError>(),
+            Some(DecodeError::DataCrcMismatch { .. })
+        ));
+
+        let options = DecodeOptions::builder()
+            .crc32(Crc32::with_algorithm(&BZIP2))
+            .build();
+        decompress_with(options, data.as_slice(), Vec::new())?;
+        Ok(())
+    }
+
+    #[test]
+    fn chunked_input() -> Result<()> {
+        for data in [FIRST, STORED, &[FIRST, STORED, SECOND].concat()] {
+            let mut expected = Vec::new();
+            decompress(data, &mut expected)?;
+            let mut output = Vec::new();
+            decompress(ChunkedReader::new(data), &mut output)?;
+            assert_eq!(output, expected);
+        }
+
+        let mut expected = Vec::new();
+        decompress(FIRST, &mut expected)?;
+        for data in [ZLIB, RAW] {
+            let mut output = Vec::new();
+            decompress_auto(ChunkedReader::new(data), &mut output)?;
+            assert_eq!(output, expected);
+        }
+
+        let data = [FIRST, b"garbage\x1f", FIRST].concat();
+        let options = DecodeOptions::builder().lenient(true).build();
+        let mut output = Vec::new();
+        let decoded = decompress_with(options, ChunkedReader::new(&data), &mut output)?;
+        assert_eq!(output, [expected.as_slice(), &expected].concat());
+        assert_eq!(decoded.failed_members.len(), 1);
+
+        let bytes = decompress_bytes(ChunkedReader::new(FIRST)).collect::<Result<Vec<_>>>()?;
+        assert_eq!(bytes, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn input_consumed() -> Result<()> {
+        let data = [FIRST, STORED, SECOND].concat();
+        let options = DecodeOptions::default();
+        let decoded = decompress_with(options.clone(), data.as_slice(), Vec::new())?;
+        assert_eq!(decoded.input_consumed, data.len() as u64);
+        let decoded = decompress_with(options, ChunkedReader::new(&data), Vec::new())?;
+        assert_eq!(decoded.input_consumed, data.len() as u64);
+        Ok(())
+    }
+
+    #[test]
+    fn decompress_one() -> Result<()> {
+        let data = [FIRST, SECOND].concat();
+        let mut input = data.as_slice();
+        let mut output = Vec::new();
+        let header = super::decompress_one(&mut input, &mut output)?;
+        assert_eq!(header.os, 3);
+        assert_eq!(input, SECOND);
+
+        let mut expected = Vec::new();
+        decompress(FIRST, &mut expected)?;
+        assert_eq!(output, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn decompress_to_file() -> Result<()> {
+        let mut expected = Vec::new();
+        decompress(FIRST, &mut expected)?;
+
+        let path = std::env::temp_dir().join(format!("ripgzip-{}", std::process::id()));
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        super::decompress_to_file(FIRST, &file, expected.len() as u64)?;
+        assert_eq!(std::fs::read(&path)?, expected);
+
+        let file = File::options().write(true).truncate(true).open(&path)?;
+        assert!(super::decompress_to_file(FIRST, &file, 1000).is_err());
+        assert_eq!(std::fs::read(&path)?, expected);
+
+        // A corrupt member leaves what was decoded, not the zero padding.
+        let mut corrupt = FIRST.to_vec();
+        corrupt.truncate(FIRST.len() / 2);
+        let file = File::options().write(true).truncate(true).open(&path)?;
+        let len = expected.len() as u64;
+        assert!(super::decompress_to_file(corrupt.as_slice(), &file, len).is_err());
+        let partial = std::fs::read(&path)?;
+        assert!(partial.len() < expected.len());
+        assert!(expected.starts_with(&partial));
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn unsupported_method() -> Result<()> {
+        let mut other = FIRST.to_vec();
+        other[2] = 9;
+        let data = [FIRST, &other, SECOND].concat();
+
+        let error = decompress(data.as_slice(), Vec::new()).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::UnsupportedMethod { method: 9 })
+        );
+
+        let options = DecodeOptions::builder().lenient(true).build();
+        let mut output = Vec::new();
+        let decoded = decompress_with(options, data.as_slice(), &mut output)?;
+        assert_eq!(decoded.members, 2);
+        assert_eq!(decoded.failed_members.len(), 1);
+        assert_eq!(decoded.failed_members[0].index, 1);
+        assert_eq!(
+            decoded.failed_members[0]
+                .error
+                .downcast_ref::<DecodeError>(),
+            Some(&DecodeError::UnsupportedMethod { method: 9 })
+        );
+
+        let mut expected = Vec::new();
+        decompress([FIRST, SECOND].concat().as_slice(), &mut expected)?;
+        assert_eq!(output, expected);
+        Ok(())
     }
-    Ok(())
 }
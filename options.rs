@@ -0,0 +1,224 @@
+#![forbid(unsafe_code)]
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+
+use crate::checksum::Crc32;
+use crate::error::DecodeError;
+use crate::gzip::MemberHeader;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Knobs for [`decompress_with`](crate::decompress_with), created through
+/// [`DecodeOptions::builder`]. The default is a strict-checksum decode that
+/// ignores trailing garbage.
+#[derive(Clone, Debug, Default)]
+pub struct DecodeOptions {
+    pub(crate) lenient: bool,
+    pub(crate) strict: bool,
+    pub(crate) ignore_checksum: bool,
+    pub(crate) max_output: Option<u64>,
+    pub(crate) max_members: Option<usize>,
+    pub(crate) lossy_text: bool,
+    pub(crate) crc32: Crc32,
+    pub(crate) future_mtime_threshold: Duration,
+    pub(crate) now: Option<SystemTime>,
+    pub(crate) flush: FlushPolicy,
+    pub(crate) unknown_os: UnknownOsPolicy,
+    pub(crate) expect_header: Option<HeaderProfile>,
+}
+
+impl DecodeOptions {
+    pub fn builder() -> DecodeOptionsBuilder {
+        DecodeOptionsBuilder::default()
+    }
+
+    /// Latest MTIME, in seconds since the epoch, that doesn't get a
+    /// [`Warning::FutureMtime`](crate::Warning).
+    pub(crate) fn latest_mtime(&self) -> u64 {
+        let now = self.now.unwrap_or_else(SystemTime::now);
+        let now = now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        now.saturating_add(self.future_mtime_threshold.as_secs())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// When decoding calls `flush` on the output writer. Flushing only batches
+/// I/O differently; it never changes what is written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// After every DEFLATE block, for latency, e.g. when writing to a socket.
+    EveryBlock,
+    /// At the first block end after at least this many bytes were written
+    /// since the last flush, and at the end of every member.
+    EveryNBytes(usize),
+    /// Once, after the last member.
+    AtEnd,
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        Self::EveryNBytes(64 * 1024)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// What to do with a member whose OS byte RFC 1952 doesn't define.
+/// [`UnknownOsPolicy::Ignore`] by default, `strict` doesn't change it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnknownOsPolicy {
+    #[default]
+    Ignore,
+    /// Report a [`Warning::UnknownOs`](crate::Warning).
+    Warn,
+    /// Fail with [`DecodeError::UnknownOs`](crate::DecodeError).
+    Reject,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Header fields every member must carry, e.g. to check in CI that gzip
+/// output is reproducible: OS 3 (Unix), MTIME 0 and XFL 2 for `gzip -n -9`.
+/// Fields left at `None` aren't checked.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeaderProfile {
+    pub os: Option<u8>,
+    pub modification_time: Option<u32>,
+    pub extra_flags: Option<u8>,
+}
+
+impl HeaderProfile {
+    /// Fail with [`DecodeError::HeaderMismatch`] on the first field of
+    /// `header` that differs from the profile.
+    pub fn check(&self, header: &MemberHeader) -> Result<()> {
+        let fields = [
+            ("OS", self.os.map(u32::from), u32::from(header.os)),
+            ("MTIME", self.modification_time, header.modification_time),
+            (
+                "XFL",
+                self.extra_flags.map(u32::from),
+                u32::from(header.extra_flags),
+            ),
+        ];
+        for (field, expected, actual) in fields {
+            match expected {
+                Some(expected) if expected != actual => bail!(DecodeError::HeaderMismatch {
+                    field,
+                    expected,
+                    actual,
+                }),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug, Default)]
+pub struct DecodeOptionsBuilder {
+    options: DecodeOptions,
+}
+
+impl DecodeOptionsBuilder {
+    /// Record a failing member in [`Decoded::failed_members`](crate::Decoded),
+    /// resynchronize on the next gzip magic and keep going instead of
+    /// aborting. Output already written for the failing member is kept.
+    pub fn lenient(mut self, value: bool) -> Self {
+        self.options.lenient = value;
+        self
+    }
+
+    /// Reject non-gzip data after the last member with
+    /// [`DecodeError::TrailingGarbage`](crate::DecodeError) instead of
+    /// ignoring it, and headers with reserved FLG bits instead of reporting
+    /// them as [`Warning`](crate::Warning)s. Unknown OS values are left to
+    /// [`unknown_os`](Self::unknown_os).
+    pub fn strict(mut self, value: bool) -> Self {
+        self.options.strict = value;
+        self
+    }
+
+    /// Turn CRC32/ISIZE mismatches into [`Warning`](crate::Warning)s and keep
+    /// the decoded output instead of failing. Useful for salvaging damaged files.
+    /// A mismatched member is still written out in full and flushed like any
+    /// other, so the output holds everything that was decoded.
+    pub fn ignore_checksum(mut self, value: bool) -> Self {
+        self.options.ignore_checksum = value;
+        self
+    }
+
+    /// Fail with [`DecodeError::OutputLimitExceeded`](crate::DecodeError)
+    /// once more than `limit` bytes would be produced in total.
+    pub fn max_output(mut self, limit: u64) -> Self {
+        self.options.max_output = Some(limit);
+        self
+    }
+
+    /// Fail with [`DecodeError::TooManyMembers`](crate::DecodeError) before
+    /// starting member number `limit + 1`. Unlimited by default; set it
+    /// together with `max_output` for untrusted input, where millions of tiny
+    /// members would otherwise keep the decoder busy.
+    pub fn max_members(mut self, limit: usize) -> Self {
+        self.options.max_members = Some(limit);
+        self
+    }
+
+    /// Decode a non-UTF-8 FNAME or FCOMMENT lossily and report it as a
+    /// [`Warning`](crate::Warning) instead of failing the member.
+    pub fn lossy_text(mut self, value: bool) -> Self {
+        self.options.lossy_text = value;
+        self
+    }
+
+    /// Check the footer CRC32 with another algorithm, e.g.
+    /// `Crc32::with_algorithm(&BZIP2)`. Gzip itself always uses the default,
+    /// CRC-32/ISO-HDLC.
+    pub fn crc32(mut self, crc32: Crc32) -> Self {
+        self.options.crc32 = crc32;
+        self
+    }
+
+    /// Only report an MTIME as [`Warning::FutureMtime`](crate::Warning) when
+    /// it is more than `threshold` after the current time, e.g. to allow for
+    /// clock skew. Zero by default.
+    pub fn future_mtime_threshold(mut self, threshold: Duration) -> Self {
+        self.options.future_mtime_threshold = threshold;
+        self
+    }
+
+    /// Compare MTIME against `now` instead of the system clock.
+    pub fn now(mut self, now: SystemTime) -> Self {
+        self.options.now = Some(now);
+        self
+    }
+
+    /// How to treat an unknown OS value, [`UnknownOsPolicy::Ignore`] by
+    /// default.
+    pub fn unknown_os(mut self, policy: UnknownOsPolicy) -> Self {
+        self.options.unknown_os = policy;
+        self
+    }
+
+    /// Check every member header against `profile`, see [`HeaderProfile`].
+    pub fn expect_header(mut self, profile: HeaderProfile) -> Self {
+        self.options.expect_header = Some(profile);
+        self
+    }
+
+    /// When to flush the output writer, see [`FlushPolicy`].
+    pub fn flush_every(mut self, policy: FlushPolicy) -> Self {
+        self.options.flush = policy;
+        self
+    }
+
+    pub fn build(self) -> DecodeOptions {
+        self.options
+    }
+}
@@ -0,0 +1,125 @@
+#![forbid(unsafe_code)]
+
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::mem;
+
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+
+use crate::decoder::Decoder;
+use crate::gzip::{ID1, ID2};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Decompress a multi-member gzip stream, inflating the members concurrently.
+///
+/// DEFLATE bodies don't record their compressed length, so the whole input is
+/// buffered and a member is decoded at every offset holding the gzip magic.
+/// Offsets inside a member usually fail within a few bytes. Walking from the
+/// start, each member's end gives the next one, so every real member is
+/// decoded once. As with [`decompress`](crate::decompress), decoding stops at
+/// trailing bytes that don't start with the magic.
+pub fn decompress_parallel<R: BufRead, W: Write>(mut input: R, mut output: W) -> Result<()> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data).context("input read")?;
+
+    let mut members = member_starts(&data)
+        .into_par_iter()
+        .map(|start| (start, decode_member(&data[start..])))
+        .collect::<HashMap<_, _>>();
+
+    let mut start = 0;
+    while let Some(member) = members.remove(&start) {
+        let (len, decoded) = member.with_context(|| format!("gzip member at byte {}", start))?;
+        output.write_all(&decoded).context("member write")?;
+        start += len;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+/// Offsets where a member could start: the first byte, which must hold one,
+/// and every later gzip magic.
+fn member_starts(data: &[u8]) -> Vec<usize> {
+    (0..data.len())
+        .filter(|&start| start == 0 || data[start..].starts_with(&[ID1, ID2]))
+        .collect()
+}
+
+/// Decode the member at the start of `data`. Returns its compressed length
+/// and output.
+fn decode_member(data: &[u8]) -> Result<(usize, Vec<u8>)> {
+    let mut input = data;
+    let decoded = {
+        let mut decoder = Decoder::new(&mut input, Vec::new());
+        decoder.decode_one()?;
+        mem::take(decoder.get_output())
+    };
+    Ok((data.len() - input.len(), decoded))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::compress_stored;
+    use crate::decompress;
+    use crate::test_data::{FIRST, SECOND};
+    use std::io;
+
+    #[test]
+    fn member_starts() {
+        let data = [FIRST, SECOND, FIRST].concat();
+        let first = FIRST.len();
+        let second = first + SECOND.len();
+        assert_eq!(super::member_starts(&data), vec![0, first, second]);
+        assert_eq!(super::member_starts(b"x"), vec![0]);
+        assert!(super::member_starts(b"").is_empty());
+    }
+
+    #[test]
+    fn matches_sequential() -> Result<()> {
+        let data = [FIRST, SECOND, FIRST, SECOND].concat();
+        let mut expected = Vec::new();
+        decompress(data.as_slice(), &mut expected)?;
+
+        let mut output = Vec::new();
+        decompress_parallel(data.as_slice(), &mut output)?;
+        assert_eq!(output, expected);
+        assert!(output.starts_with(b"line 0 of the first member\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn magic_inside_member() -> Result<()> {
+        // A stored block holding a whole gzip member decodes fine from the
+        // inner offset too, but isn't a member of the outer stream.
+        let mut stored = Vec::new();
+        compress_stored(FIRST, &mut stored)?;
+        let data = [&stored, SECOND].concat();
+        let mut expected = Vec::new();
+        decompress(data.as_slice(), &mut expected)?;
+
+        let mut output = Vec::new();
+        decompress_parallel(data.as_slice(), &mut output)?;
+        assert_eq!(output, expected);
+        assert!(output.starts_with(FIRST));
+        Ok(())
+    }
+
+    #[test]
+    fn trailing_garbage() -> Result<()> {
+        let mut expected = Vec::new();
+        decompress([FIRST, SECOND].concat().as_slice(), &mut expected)?;
+
+        let data = [FIRST, SECOND, b"\0\0 trailing \x1f\x8b"].concat();
+        let mut output = Vec::new();
+        decompress_parallel(data.as_slice(), &mut output)?;
+        assert_eq!(output, expected);
+
+        assert!(decompress_parallel(&b"not gzip"[..], io::sink()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+#![forbid(unsafe_code)]
+
+use crate::deflate::{BlockInfo, CompressionType};
+use crate::error::DecodeError;
+use crate::gzip::{MemberFooter, MemberHeader};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Summary of a [`decompress_with`](crate::decompress_with) run.
+#[derive(Debug, Default)]
+pub struct Decoded {
+    /// Members that were decoded, including ones with tolerated problems.
+    pub members: usize,
+    /// Header of every member that got past header parsing, in stream order.
+    /// In lenient mode this includes members whose body then failed.
+    pub headers: Vec<MemberHeader>,
+    /// Footer of every member that passed its footer check, in stream order.
+    pub footers: Vec<MemberFooter>,
+    /// Members skipped in lenient mode, in stream order.
+    pub failed_members: Vec<MemberFailure>,
+    /// Problems that were tolerated because of the decode options.
+    pub warnings: Vec<Warning>,
+    /// Compressed bytes read, headers and footers included. Trailing data
+    /// that was ignored is not counted.
+    pub input_consumed: u64,
+}
+
+/// A member that failed to decode.
+#[derive(Debug)]
+pub struct MemberFailure {
+    /// Zero-based position of the member in the stream.
+    pub index: usize,
+    /// Why the member was rejected.
+    pub error: anyhow::Error,
+}
+
+/// A tolerated problem, tagged with the zero-based member index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// The footer CRC32 or ISIZE didn't match (with `ignore_checksum`).
+    FooterMismatch { member: usize, error: DecodeError },
+    /// The header sets FLG bits that RFC 1952 reserves.
+    ReservedFlags { member: usize, flags: u8 },
+    /// The header's OS value isn't one RFC 1952 defines.
+    UnknownOs { member: usize, os: u8 },
+    /// MTIME lies after the time of decoding.
+    FutureMtime { member: usize, mtime: u32 },
+    /// FNAME or FCOMMENT wasn't valid UTF-8 (with `lossy_text`).
+    LossyText { member: usize },
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// How a stream was compressed, from
+/// [`decompress_with_stats`](crate::decompress_with_stats).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DecodeStats {
+    pub members: usize,
+    /// Compressed bytes read, headers and footers included.
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub stored_blocks: usize,
+    pub fixed_blocks: usize,
+    pub dynamic_blocks: usize,
+    /// Smallest decoded size of a block, 0 if there were no blocks.
+    pub min_block_output: u64,
+    pub max_block_output: u64,
+}
+
+impl DecodeStats {
+    pub fn blocks(&self) -> usize {
+        self.stored_blocks + self.fixed_blocks + self.dynamic_blocks
+    }
+
+    /// Mean decoded size of a block, 0 if there were no blocks.
+    pub fn mean_block_output(&self) -> f64 {
+        match self.blocks() {
+            0 => 0.0,
+            blocks => self.output_bytes as f64 / blocks as f64,
+        }
+    }
+
+    /// Output bytes per input byte, `None` if there was no input.
+    pub fn compression_ratio(&self) -> Option<f64> {
+        match self.input_bytes {
+            0 => None,
+            input_bytes => Some(self.output_bytes as f64 / input_bytes as f64),
+        }
+    }
+
+    pub(crate) fn record(&mut self, block: &BlockInfo) {
+        let size = block.decoded_bytes;
+        if self.blocks() == 0 {
+            self.min_block_output = size;
+        }
+        self.min_block_output = self.min_block_output.min(size);
+        self.max_block_output = self.max_block_output.max(size);
+        self.output_bytes += size;
+        match block.compression_type {
+            CompressionType::Uncompressed => self.stored_blocks += 1,
+            CompressionType::FixedTree => self.fixed_blocks += 1,
+            CompressionType::DynamicTree => self.dynamic_blocks += 1,
+            CompressionType::Reserved => {}
+        }
+    }
+}
@@ -0,0 +1,72 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, BufRead};
+
+use anyhow::{Context, Result};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Where a [`DeflateReader`](crate::DeflateReader) puts what it decodes.
+///
+/// [`TrackingWriter`](crate::TrackingWriter) implements this for any
+/// `io::Write`, keeping the 32 KiB of history back-references need. Sinks
+/// that can't fail and don't buffer, like a rolling hash or a byte counter,
+/// can implement it directly and skip `io::Write` altogether. They get every
+/// back-reference as is, so they keep whatever history they need themselves.
+///
+/// The methods return `Result`, not `()`, because `TrackingWriter` writes
+/// through to an `io::Write` that can fail and the decoder has to stop
+/// then. A sink that can't fail always returns `Ok`.
+pub trait OutputSink {
+    /// Append literal bytes, from a Huffman literal or a stored block.
+    fn push(&mut self, bytes: &[u8]) -> Result<()>;
+
+    /// Append `len` bytes copied from `dist` bytes back. `len` may exceed
+    /// `dist`, in which case the copy repeats the last `dist` bytes.
+    fn push_back_reference(&mut self, dist: usize, len: usize) -> Result<()>;
+
+    /// Append up to `len` bytes read from `input`, the body of a stored
+    /// block, stopping early at its end. Returns how many were appended.
+    /// The default pushes straight out of `input`'s buffer.
+    fn push_from(&mut self, input: &mut dyn BufRead, len: usize) -> Result<usize> {
+        let mut copied = 0;
+        while copied < len {
+            let buffer = match input.fill_buf() {
+                Ok(buffer) => buffer,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error).context("uncompressed copy"),
+            };
+            if buffer.is_empty() {
+                break;
+            }
+            let n = buffer.len().min(len - copied);
+            self.push(&buffer[..n])?;
+            input.consume(n);
+            copied += n;
+        }
+        Ok(copied)
+    }
+
+    /// Called when the [`FlushPolicy`](crate::FlushPolicy) asks for it.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: OutputSink + ?Sized> OutputSink for &mut S {
+    fn push(&mut self, bytes: &[u8]) -> Result<()> {
+        (**self).push(bytes)
+    }
+
+    fn push_back_reference(&mut self, dist: usize, len: usize) -> Result<()> {
+        (**self).push_back_reference(dist, len)
+    }
+
+    fn push_from(&mut self, input: &mut dyn BufRead, len: usize) -> Result<usize> {
+        (**self).push_from(input, len)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+}
@@ -0,0 +1,147 @@
+#![forbid(unsafe_code)]
+
+use std::io::{Read, Write};
+
+use anyhow::{ensure, Context, Result};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::bit_reader::BitSequence;
+
+////////////////////////////////////////////////////////////////////////////////
+
+const STATE_MAGIC: &[u8; 4] = b"RGZS";
+const STATE_VERSION: u8 = 1;
+const MAX_HISTORY: usize = 32768;
+
+/// Snapshot of a [`DeflateReader`](crate::DeflateReader) between two blocks, for pausing a long
+/// decode and picking it up later, possibly in another process.
+///
+/// Only the DEFLATE layer is covered: a gzip or zlib wrapper has to be
+/// tracked by the caller. The running checksum is kept as its current value,
+/// since a CRC digest can't be stored directly.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecoderState {
+    /// Up to the last 32 KiB of output, oldest first.
+    pub history: Vec<u8>,
+    /// Checksum of the output so far, as
+    /// [`Checksum::finalize`](crate::Checksum::finalize) returns it.
+    pub checksum: u32,
+    /// Output bytes since the last member boundary.
+    pub byte_count: u64,
+    /// Output bytes over the lifetime of the reader, for the output limit.
+    pub total_output: u64,
+    /// Bits consumed from the input.
+    pub bit_position: u64,
+    /// Bits read from the input but not consumed yet.
+    pub buffered_bits: BitSequence,
+}
+
+impl DecoderState {
+    /// Byte offset in the original input where the input handed to
+    /// [`DeflateReader::restore_state`](crate::DeflateReader::restore_state)
+    /// has to start.
+    pub fn input_offset(&self) -> u64 {
+        (self.bit_position + u64::from(self.buffered_bits.len())) / 8
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(STATE_MAGIC)?;
+        writer.write_u8(STATE_VERSION)?;
+        writer.write_u64::<LittleEndian>(self.bit_position)?;
+        writer.write_u16::<LittleEndian>(self.buffered_bits.bits())?;
+        writer.write_u8(self.buffered_bits.len())?;
+        writer.write_u64::<LittleEndian>(self.total_output)?;
+        writer.write_u64::<LittleEndian>(self.byte_count)?;
+        writer.write_u32::<LittleEndian>(self.checksum)?;
+        writer.write_u32::<LittleEndian>(self.history.len() as u32)?;
+        writer.write_all(&self.history)?;
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic).context("state magic")?;
+        ensure!(&magic == STATE_MAGIC, "not a decoder state");
+        let version = reader.read_u8()?;
+        ensure!(
+            version == STATE_VERSION,
+            "unsupported decoder state version {}",
+            version
+        );
+        let bit_position = reader.read_u64::<LittleEndian>()?;
+        let bits = reader.read_u16::<LittleEndian>()?;
+        let len = reader.read_u8()?;
+        ensure!(len < 16, "bad buffered bit count {}", len);
+        let total_output = reader.read_u64::<LittleEndian>()?;
+        let byte_count = reader.read_u64::<LittleEndian>()?;
+        let checksum = reader.read_u32::<LittleEndian>()?;
+        let history_len = reader.read_u32::<LittleEndian>()? as usize;
+        ensure!(history_len <= MAX_HISTORY, "history too long");
+        let mut history = vec![0; history_len];
+        reader.read_exact(&mut history).context("state history")?;
+        Ok(Self {
+            history,
+            checksum,
+            byte_count,
+            total_output,
+            bit_position,
+            buffered_bits: BitSequence::new(bits, len),
+        })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_reader::BitReader;
+    use crate::bit_writer::BitWriter;
+    use crate::checksum::ALGORITHM;
+    use crate::deflate::DeflateReader;
+    use crate::tracking_writer::TrackingWriter;
+
+    /// A non-final fixed block with "abc", then a final one copying it.
+    fn two_blocks() -> Result<Vec<u8>> {
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(BitSequence::new(0b010, 3))?;
+        for byte in b"abc" {
+            writer.write_bits(BitSequence::from_msb(0x30 + u16::from(*byte), 8))?;
+        }
+        writer.write_bits(BitSequence::from_msb(0, 7))?;
+        writer.write_bits(BitSequence::new(0b011, 3))?;
+        writer.write_bits(BitSequence::from_msb(1, 7))?;
+        writer.write_bits(BitSequence::from_msb(2, 5))?;
+        writer.write_bits(BitSequence::from_msb(0, 7))?;
+        Ok(writer.borrow_writer_from_boundary()?.clone())
+    }
+
+    #[test]
+    fn save_and_restore() -> Result<()> {
+        let data = two_blocks()?;
+        let mut deflate = DeflateReader::new(
+            BitReader::new(data.as_slice()),
+            TrackingWriter::new(Vec::new()),
+        );
+        assert!(!deflate.next_block()?);
+        let mut saved = Vec::new();
+        deflate.save_state()?.write_to(&mut saved)?;
+        assert_eq!(deflate.get_output(), b"abc");
+
+        let state = DecoderState::read_from(saved.as_slice())?;
+        assert_eq!(state.input_offset(), 5);
+        let input = &data[state.input_offset() as usize..];
+        let mut deflate =
+            DeflateReader::new(BitReader::new(input), TrackingWriter::new(Vec::new()));
+        deflate.restore_state(&state);
+        assert!(deflate.next_block()?);
+        assert_eq!(deflate.get_output(), b"abc");
+        deflate.check_crc32_and_isize(ALGORITHM.checksum(b"abcabc"), 6)?;
+        Ok(())
+    }
+
+    #[test]
+    fn not_a_state() {
+        assert!(DecoderState::read_from(&b"RGZX"[..]).is_err());
+    }
+}
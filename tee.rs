@@ -0,0 +1,83 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, Write};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Writes everything to two sinks, e.g. `decompress(input, TeeWriter::new(file, hasher))`.
+///
+/// Each chunk goes to `first` and then to `second`. If `first` fails,
+/// `second` doesn't see the chunk. Errors keep their kind and say which
+/// sink failed.
+pub struct TeeWriter<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: Write, B: Write> TeeWriter<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+fn sink_error(sink: &str, error: io::Error) -> io::Error {
+    io::Error::new(error.kind(), format!("{} sink: {}", sink, error))
+}
+
+impl<A: Write, B: Write> Write for TeeWriter<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.first
+            .write_all(buf)
+            .map_err(|error| sink_error("first", error))?;
+        self.second
+            .write_all(buf)
+            .map_err(|error| sink_error("second", error))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.first
+            .flush()
+            .map_err(|error| sink_error("first", error))?;
+        self.second
+            .flush()
+            .map_err(|error| sink_error("second", error))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompress;
+    use crate::test_data::FIRST;
+    use anyhow::Result;
+
+    #[test]
+    fn both_sinks() -> Result<()> {
+        let mut expected = Vec::new();
+        decompress(FIRST, &mut expected)?;
+
+        let mut tee = TeeWriter::new(Vec::new(), Vec::new());
+        decompress(FIRST, &mut tee)?;
+        let (first, second) = tee.into_inner();
+        assert_eq!(first, expected);
+        assert_eq!(second, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn failing_sink() {
+        let mut full = [0u8; 100];
+        let mut tee = TeeWriter::new(Vec::new(), full.as_mut_slice());
+        let error = decompress(FIRST, &mut tee).unwrap_err();
+        let error = error.downcast_ref::<io::Error>().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::WriteZero);
+        assert!(error.to_string().starts_with("second sink: "));
+    }
+}
@@ -0,0 +1,95 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, BufRead, Read};
+
+/// 20 lines of text, one dynamic-Huffman block.
+pub const FIRST: &[u8] = &[
+    31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 125, 208, 75, 10, 128, 48, 16, 4, 209, 189, 167, 152, 35, 216,
+    254, 162, 215, 17, 38, 24, 72, 12, 196, 220, 31, 113, 111, 247, 186, 86, 245, 114, 186, 221,
+    70, 171, 209, 250, 229, 22, 83, 123, 186, 21, 47, 167, 183, 33, 127, 9, 60, 77, 60, 205, 60,
+    45, 60, 173, 60, 109, 60, 5, 158, 118, 158, 14, 177, 172, 56, 132, 7, 4, 8, 132, 8, 4, 9, 132,
+    9, 4, 10, 132, 10, 4, 11, 254, 93, 94, 190, 233, 65, 120, 38, 2, 0, 0,
+];
+
+/// 15 lines of text, one dynamic-Huffman block.
+pub const SECOND: &[u8] = &[
+    31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 117, 207, 185, 17, 128, 48, 12, 0, 193, 220, 85, 168, 4, 252,
+    34, 181, 3, 40, 180, 153, 177, 73, 232, 158, 6, 142, 116, 179, 93, 126, 222, 227, 146, 238,
+    253, 240, 41, 62, 158, 249, 202, 22, 22, 232, 142, 26, 11, 114, 138, 204, 138, 156, 43, 114,
+    73, 204, 134, 92, 27, 114, 203, 188, 249, 73, 242, 82, 121, 105, 188, 52, 13, 31, 6, 124, 238,
+    61, 87, 1, 0, 0,
+];
+
+/// `FIRST`'s payload wrapped in zlib framing.
+pub const ZLIB: &[u8] = &[
+    120, 218, 125, 208, 75, 10, 128, 48, 16, 4, 209, 189, 167, 152, 35, 216, 254, 162, 215, 17, 38,
+    24, 72, 12, 196, 220, 31, 113, 111, 247, 186, 86, 245, 114, 186, 221, 70, 171, 209, 250, 229,
+    22, 83, 123, 186, 21, 47, 167, 183, 33, 127, 9, 60, 77, 60, 205, 60, 45, 60, 173, 60, 109, 60,
+    5, 158, 118, 158, 14, 177, 172, 56, 132, 7, 4, 8, 132, 8, 4, 9, 132, 9, 4, 10, 132, 10, 4, 11,
+    254, 93, 94, 131, 123, 186, 165,
+];
+
+/// `FIRST`'s payload as a bare DEFLATE stream.
+pub const RAW: &[u8] = &[
+    125, 208, 75, 10, 128, 48, 16, 4, 209, 189, 167, 152, 35, 216, 254, 162, 215, 17, 38, 24, 72,
+    12, 196, 220, 31, 113, 111, 247, 186, 86, 245, 114, 186, 221, 70, 171, 209, 250, 229, 22, 83,
+    123, 186, 21, 47, 167, 183, 33, 127, 9, 60, 77, 60, 205, 60, 45, 60, 173, 60, 109, 60, 5, 158,
+    118, 158, 14, 177, 172, 56, 132, 7, 4, 8, 132, 8, 4, 9, 132, 9, 4, 10, 132, 10, 4, 11, 254, 93,
+    94,
+];
+
+/// Three lines of text in a single stored block.
+pub const STORED: &[u8] = &[
+    31, 139, 8, 0, 0, 0, 0, 0, 4, 3, 1, 63, 0, 192, 255, 115, 116, 111, 114, 101, 100, 32, 98, 108,
+    111, 99, 107, 32, 112, 97, 121, 108, 111, 97, 100, 10, 115, 116, 111, 114, 101, 100, 32, 98,
+    108, 111, 99, 107, 32, 112, 97, 121, 108, 111, 97, 100, 10, 115, 116, 111, 114, 101, 100, 32,
+    98, 108, 111, 99, 107, 32, 112, 97, 121, 108, 111, 97, 100, 10, 2, 102, 24, 106, 63, 0, 0, 0,
+];
+
+/// A member with no data: one empty final stored block.
+pub const EMPTY: &[u8] = &[
+    31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 1, 0, 0, 255, 255, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+/// Two BGZF blocks (63 and 26 bytes of text) and the BGZF EOF marker.
+#[cfg(feature = "bgzf")]
+pub const BGZF: &[u8] = &[
+    31, 139, 8, 4, 0, 0, 0, 0, 0, 255, 6, 0, 66, 67, 2, 0, 51, 0, 203, 72, 205, 201, 201, 87, 72,
+    74, 175, 74, 83, 72, 202, 201, 79, 206, 86, 200, 207, 75, 229, 202, 32, 90, 16, 0, 20, 157,
+    155, 8, 63, 0, 0, 0, 31, 139, 8, 4, 0, 0, 0, 0, 0, 255, 6, 0, 66, 67, 2, 0, 43, 0, 43, 78, 77,
+    206, 207, 75, 81, 72, 202, 201, 79, 206, 230, 42, 70, 230, 0, 0, 171, 159, 224, 242, 26, 0, 0,
+    0, 31, 139, 8, 4, 0, 0, 0, 0, 0, 255, 6, 0, 66, 67, 2, 0, 27, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Hands out its data 1 to 3 bytes per `fill_buf`, like a throttled stream.
+pub struct ChunkedReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ChunkedReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+}
+
+impl Read for ChunkedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.fill_buf()?.read(buf)?;
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl BufRead for ChunkedReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let end = self.data.len().min(self.position + self.position % 3 + 1);
+        Ok(&self.data[self.position..end])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.position += amt;
+    }
+}
@@ -0,0 +1,74 @@
+use std::io::Write;
+
+use anyhow::Result;
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::Compression;
+use proptest::prelude::*;
+use ripgzip::{decompress, inflate, inflate_blocks, CompressionType};
+
+/// Mostly short runs over a small alphabet, so the encoder finds matches at
+/// every distance and builds both fixed and dynamic blocks.
+fn data() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        prop::collection::vec(any::<u8>(), 0..4096),
+        prop::collection::vec(0u8..4, 0..40_000),
+        prop::collection::vec((0u8..16, 1usize..300), 0..200).prop_map(|runs| {
+            runs.into_iter()
+                .flat_map(|(byte, len)| std::iter::repeat_n(byte, len))
+                .collect()
+        }),
+    ]
+}
+
+fn gzip(data: &[u8], level: u32) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn deflate(data: &[u8], level: u32) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::new(level));
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+proptest! {
+    #[test]
+    fn gzip_roundtrip(data in data(), level in 0u32..=9) {
+        let mut output = Vec::new();
+        decompress(gzip(&data, level).as_slice(), &mut output).unwrap();
+        prop_assert_eq!(output, data);
+    }
+
+    #[test]
+    fn deflate_roundtrip(data in data(), level in 0u32..=9) {
+        let mut output = Vec::new();
+        inflate(deflate(&data, level).as_slice(), &mut output).unwrap();
+        prop_assert_eq!(output, data);
+    }
+
+    #[test]
+    fn multi_member_roundtrip(members in prop::collection::vec((data(), 0u32..=9), 1..5)) {
+        let input: Vec<u8> = members.iter().flat_map(|(data, level)| gzip(data, *level)).collect();
+        let expected: Vec<u8> = members.iter().flat_map(|(data, _)| data.clone()).collect();
+        let mut output = Vec::new();
+        let count = decompress(input.as_slice(), &mut output).unwrap();
+        prop_assert_eq!(count, members.len());
+        prop_assert_eq!(output, expected);
+    }
+}
+
+/// The inputs above do reach all three block types.
+#[test]
+fn block_types() -> Result<()> {
+    let block_types = |data: &[u8], level| -> Result<Vec<CompressionType>> {
+        inflate_blocks(deflate(data, level).as_slice())
+            .map(|block| Ok(block?.compression_type))
+            .collect()
+    };
+    let text = b"abcabcabd".repeat(1000);
+    assert!(block_types(&text, 0)?.contains(&CompressionType::Uncompressed));
+    assert!(block_types(b"abcabcabd", 6)?.contains(&CompressionType::FixedTree));
+    assert!(block_types(&text, 9)?.contains(&CompressionType::DynamicTree));
+    Ok(())
+}
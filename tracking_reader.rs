@@ -0,0 +1,185 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, BufRead, Read};
+
+use crate::checksum::{Checksum, Crc32};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Counts the bytes pulled out of the wrapped reader, and optionally takes
+/// their CRC32, e.g. to key a cache on the compressed input.
+pub struct TrackingReader<T> {
+    inner: T,
+    byte_counter: u64,
+    crc32: Option<Crc32>,
+    crc32_error: Option<io::Error>,
+}
+
+impl<T: Read> Read for TrackingReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.byte_counter += read as u64;
+        if let Some(crc32) = &mut self.crc32 {
+            crc32.update(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+impl<T: BufRead> BufRead for TrackingReader<T> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.byte_counter += amt as u64;
+        if let Some(crc32) = &mut self.crc32 {
+            // The buffer is already filled, so this hands it out again
+            // without reading. If it fails anyway, the digest misses these
+            // bytes and `crc32` reports that.
+            match self.inner.fill_buf() {
+                Ok(buffer) => crc32.update(&buffer[..amt.min(buffer.len())]),
+                Err(error) => {
+                    self.crc32_error.get_or_insert(error);
+                }
+            }
+        }
+        self.inner.consume(amt)
+    }
+}
+
+impl<T> TrackingReader<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            byte_counter: 0,
+            crc32: None,
+            crc32_error: None,
+        }
+    }
+
+    /// Like [`TrackingReader::new`], also taking the CRC32 of everything read.
+    pub fn with_crc32(inner: T) -> Self {
+        Self {
+            crc32: Some(Crc32::default()),
+            ..Self::new(inner)
+        }
+    }
+
+    pub fn byte_count(&self) -> u64 {
+        self.byte_counter
+    }
+
+    /// CRC32 of the bytes read so far, if built [`with_crc32`](Self::with_crc32).
+    /// Fails if the inner reader couldn't hand out consumed bytes again, so
+    /// they never made it into the digest.
+    pub fn crc32(&self) -> io::Result<Option<u32>> {
+        if let Some(error) = &self.crc32_error {
+            return Err(io::Error::new(
+                error.kind(),
+                format!("input CRC32 is missing consumed bytes: {}", error),
+            ));
+        }
+        Ok(self.crc32.as_ref().map(Checksum::finalize))
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompress;
+    use crate::test_data::FIRST;
+    use anyhow::Result;
+    use byteorder::ReadBytesExt;
+
+    #[test]
+    fn byte_count() -> io::Result<()> {
+        let data: &[u8] = &[1, 2, 3, 4, 5, 6, 7, 8];
+        let mut reader = TrackingReader::new(data);
+
+        assert_eq!(reader.read_u8()?, 1);
+        assert_eq!(reader.byte_count(), 1);
+
+        let mut buf = [0u8; 3];
+        reader.read_exact(&mut buf)?;
+        assert_eq!(reader.byte_count(), 4);
+
+        assert_eq!(reader.fill_buf()?, &[5, 6, 7, 8]);
+        assert_eq!(reader.byte_count(), 4);
+        reader.consume(2);
+        assert_eq!(reader.byte_count(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn crc32() -> Result<()> {
+        let data = [FIRST, b"trailer"].concat();
+        let mut reader = TrackingReader::with_crc32(data.as_slice());
+        decompress(&mut reader, io::sink())?;
+        assert_eq!(reader.byte_count(), FIRST.len() as u64);
+        let mut expected = Crc32::default();
+        expected.update(FIRST);
+        assert_eq!(reader.crc32()?, Some(expected.finalize()));
+
+        let mut reader = TrackingReader::with_crc32(&data[..4]);
+        reader.read_exact(&mut [0; 2])?;
+        let mut expected = Crc32::default();
+        expected.update(&data[..2]);
+        assert_eq!(reader.crc32()?, Some(expected.finalize()));
+        assert_eq!(TrackingReader::new(FIRST).crc32()?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn crc32_refill_error() -> io::Result<()> {
+        struct FailingRefill {
+            data: &'static [u8],
+            fills: usize,
+        }
+
+        impl Read for FailingRefill {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.data.read(buf)
+            }
+        }
+
+        impl BufRead for FailingRefill {
+            fn fill_buf(&mut self) -> io::Result<&[u8]> {
+                self.fills += 1;
+                if self.fills > 1 {
+                    return Err(io::Error::other("refill"));
+                }
+                Ok(self.data)
+            }
+
+            fn consume(&mut self, amt: usize) {
+                self.data = &self.data[amt..];
+            }
+        }
+
+        let inner = FailingRefill {
+            data: &[1, 2, 3, 4],
+            fills: 0,
+        };
+        let mut reader = TrackingReader::with_crc32(inner);
+        assert_eq!(reader.fill_buf()?, &[1, 2, 3, 4]);
+        reader.consume(2);
+        assert_eq!(reader.byte_count(), 2);
+        assert_eq!(reader.crc32().unwrap_err().kind(), io::ErrorKind::Other);
+        Ok(())
+    }
+}
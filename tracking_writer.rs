@@ -1,30 +1,31 @@
 #![forbid(unsafe_code)]
 
-use std::cmp::min;
 use std::collections::VecDeque;
-use std::io::{self, Write};
+use std::io::{self, BufRead, IoSlice, Read, Write};
+use std::ops::Range;
 
-use anyhow::{anyhow, Context, Result};
-use crc::Digest;
-use crc::CRC_32_ISO_HDLC;
+use anyhow::{bail, Context, Result};
+
+use crate::checksum::{Checksum, Crc32};
+use crate::error::DecodeError;
+use crate::sink::OutputSink;
 
 ////////////////////////////////////////////////////////////////////////////////
 
 const HISTORY_SIZE: usize = 32768;
-pub const ALGORITHM: crc::Crc<u32> = crc::Crc::<u32>::new(&CRC_32_ISO_HDLC);
 
-pub struct TrackingWriter<T> {
+pub struct TrackingWriter<T, C = Crc32> {
     inner: T,
     buffer: VecDeque<u8>,
     byte_counter: usize,
-    digest: Digest<'static, u32>,
+    checksum: C,
 }
 
-impl<T: Write> Write for TrackingWriter<T> {
+impl<T: Write, C: Checksum> Write for TrackingWriter<T, C> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let to_write = self.inner.write(buf)?;
         self.byte_counter += to_write;
-        self.digest.update((*buf).get(0..to_write).unwrap());
+        self.checksum.update((*buf).get(0..to_write).unwrap());
         self.buffer.extend((*buf).get(0..to_write).unwrap());
         if self.buffer.len() >= HISTORY_SIZE {
             self.buffer.drain(0..self.buffer.len() - HISTORY_SIZE);
@@ -37,35 +38,94 @@ impl<T: Write> Write for TrackingWriter<T> {
     }
 }
 
+impl<T: Write, C: Checksum> OutputSink for TrackingWriter<T, C> {
+    fn push(&mut self, bytes: &[u8]) -> Result<()> {
+        self.write_all(bytes)?;
+        Ok(())
+    }
+
+    fn push_back_reference(&mut self, dist: usize, len: usize) -> Result<()> {
+        self.write_previous(dist, len)
+    }
+
+    /// Goes through `io::copy` over a `Take`, which does better than pushing
+    /// buffer by buffer when `input` has a small buffer of its own.
+    fn push_from(&mut self, input: &mut dyn BufRead, len: usize) -> Result<usize> {
+        let mut body = Read::take(input, len as u64);
+        let copied = io::copy(&mut body, self).context("uncompressed copy")?;
+        Ok(copied as usize)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Write::flush(self)?;
+        Ok(())
+    }
+}
+
 impl<T: Write> TrackingWriter<T> {
     pub fn new(inner: T) -> Self {
+        Self::with_checksum(inner, Crc32::default())
+    }
+
+    /// CRC32 of everything written since the last `clear`. Cheap enough to
+    /// ask for on every progress update, see [`Crc32`].
+    pub fn crc32(&mut self) -> u32 {
+        self.checksum()
+    }
+
+    /// Give up the writer for its final CRC32.
+    pub fn into_crc32(self) -> u32 {
+        self.checksum.into_value()
+    }
+}
+
+impl<T: Write, C: Checksum> TrackingWriter<T, C> {
+    pub fn with_checksum(inner: T, checksum: C) -> Self {
         Self {
             inner,
             buffer: VecDeque::<u8>::new(),
             byte_counter: 0,
-            digest: ALGORITHM.digest(),
+            checksum,
         }
     }
 
     pub fn clear(&mut self) -> Result<()> {
-        self.buffer = VecDeque::<u8>::new();
+        self.buffer.clear();
         self.byte_counter = 0;
-        self.digest = ALGORITHM.digest();
+        self.checksum.reset();
         Ok(())
     }
 
+    /// Swap in a new sink and `clear`, keeping the history allocation.
+    /// Returns the previous sink.
+    pub fn reset(&mut self, inner: T) -> Result<T> {
+        self.clear()?;
+        Ok(std::mem::replace(&mut self.inner, inner))
+    }
+
     /// Write a sequence of `len` bytes written `dist` bytes ago.
+    /// Fails with [`DecodeError::DistanceTooFar`] if that reaches before the
+    /// first byte written since the last `clear`.
     pub fn write_previous(&mut self, dist: usize, len: usize) -> Result<()> {
         if self.buffer.len() < dist {
-            return Err(anyhow!("bad len in write previous"));
+            bail!(DecodeError::DistanceTooFar {
+                distance: dist,
+                available: self.buffer.len(),
+            });
+        }
+        if len <= dist {
+            let start = self.buffer.len() - dist;
+            return self
+                .write_from_history(start, len)
+                .context("write all failed");
         }
+        // The run repeats the last `dist` bytes. It is collected before
+        // anything is written, so trimming the history to 32 KiB while
+        // writing can't shift the bytes it is copied from.
         self.write_all(
             &(self
                 .buffer
-                .range(
-                    self.buffer.len() - dist
-                        ..min(self.buffer.len(), self.buffer.len() - dist + len),
-                )
+                .range(self.buffer.len() - dist..)
                 .copied()
                 .cycle()
                 .take(len)
@@ -75,12 +135,93 @@ impl<T: Write> TrackingWriter<T> {
         Ok(())
     }
 
+    /// Copy `len` bytes of history starting at `start` to the sink, passing
+    /// them straight from the ring buffer with vectored writes.
+    fn write_from_history(&mut self, start: usize, len: usize) -> io::Result<()> {
+        let end = start + len;
+        let mut written = start;
+        while written < end {
+            let (first, second) = history_slices(&self.buffer, written..end);
+            let slices = [IoSlice::new(first), IoSlice::new(second)];
+            let count = match self.inner.write_vectored(&slices) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(count) => count,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            };
+            let (first, second) = history_slices(&self.buffer, written..written + count);
+            self.checksum.update(first);
+            self.checksum.update(second);
+            self.byte_counter += count;
+            // Appending leaves the indices of the source bytes unchanged.
+            for i in written..written + count {
+                self.buffer.push_back(self.buffer[i]);
+            }
+            written += count;
+        }
+        if self.buffer.len() >= HISTORY_SIZE {
+            self.buffer.drain(0..self.buffer.len() - HISTORY_SIZE);
+        }
+        Ok(())
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
     pub fn byte_count(&self) -> u32 {
         self.byte_counter as u32
     }
 
-    pub fn crc32(&mut self) -> u32 {
-        self.digest.clone().finalize()
+    pub fn checksum(&self) -> u32 {
+        self.checksum.finalize()
+    }
+
+    /// Bytes written since the last `clear`, without ISIZE's wrap-around.
+    pub(crate) fn total_byte_count(&self) -> u64 {
+        self.byte_counter as u64
+    }
+
+    /// Pick up where a writer with this history, byte count and checksum
+    /// value left off. Nothing is written to the sink.
+    pub(crate) fn restore(&mut self, history: &[u8], byte_count: u64, checksum: u32) {
+        self.buffer.clear();
+        self.buffer.extend(history);
+        self.byte_counter = byte_count as usize;
+        self.checksum.resume(checksum);
+    }
+
+    /// Let back-references reach into `dictionary` as if it had just been
+    /// written (zlib's `inflateSetDictionary`). Only its last 32 KiB can be
+    /// reached, so only those are copied; nothing is written to the sink and
+    /// the byte count and checksum stay as they are.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        let tail = &dictionary[dictionary.len().saturating_sub(HISTORY_SIZE)..];
+        self.buffer.clear();
+        self.buffer.extend(tail);
+    }
+
+    /// The window `write_previous` copies from: up to the last 32 KiB
+    /// written since the last `clear`, oldest first.
+    pub fn history(&self) -> impl Iterator<Item = u8> + '_ {
+        self.buffer.iter().copied()
+    }
+}
+
+/// `range` of `buffer` as at most two contiguous slices.
+fn history_slices(buffer: &VecDeque<u8>, range: Range<usize>) -> (&[u8], &[u8]) {
+    let (front, back) = buffer.as_slices();
+    let split = front.len();
+    if range.end <= split {
+        (&front[range], &[])
+    } else if range.start >= split {
+        (&back[range.start - split..range.end - split], &[])
+    } else {
+        (&front[range.start..], &back[..range.end - split])
     }
 }
 
@@ -89,6 +230,7 @@ impl<T: Write> TrackingWriter<T> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::checksum::{Adler32, NoChecksum};
     use byteorder::WriteBytesExt;
 
     #[test]
@@ -108,10 +250,96 @@ mod tests {
         assert_eq!(writer.write(&[42, 124, 234, 27])?, 0);
         assert_eq!(writer.byte_count(), 10);
         assert_eq!(writer.crc32(), 2992191065);
+        assert_eq!(writer.into_crc32(), 2992191065);
 
         Ok(())
     }
 
+    #[test]
+    fn set_dictionary() -> Result<()> {
+        // 32 KiB of dots and "kept" after seven bytes that don't fit.
+        let mut dictionary = b"dropped".to_vec();
+        dictionary.extend(vec![b'.'; HISTORY_SIZE - 4]);
+        dictionary.extend(b"kept");
+        let mut writer = TrackingWriter::new(Vec::new());
+        writer.set_dictionary(&dictionary);
+        assert_eq!(writer.history().count(), HISTORY_SIZE);
+        assert!(writer.history().eq(dictionary[7..].iter().copied()));
+
+        writer.write_previous(4, 4)?;
+        writer.write_previous(HISTORY_SIZE, 3)?;
+        assert_eq!(writer.get_ref(), b"kept...");
+        assert_eq!(writer.byte_count(), 7);
+        let mut plain = TrackingWriter::new(io::sink());
+        plain.write_all(b"kept...")?;
+        assert_eq!(writer.crc32(), plain.crc32());
+        Ok(())
+    }
+
+    #[test]
+    fn history() -> Result<()> {
+        let mut writer = TrackingWriter::new(Vec::new());
+        writer.write_all(b"abc")?;
+        writer.write_previous(2, 3)?;
+        assert!(writer.history().eq(b"abcbcb".iter().copied()));
+
+        writer.write_all(&vec![7; HISTORY_SIZE])?;
+        assert_eq!(writer.history().count(), HISTORY_SIZE);
+        assert!(writer.history().all(|byte| byte == 7));
+
+        writer.clear()?;
+        assert_eq!(writer.history().next(), None);
+        Ok(())
+    }
+
+    /// Keeps everything and counts calls, taking all slices of a vectored write.
+    #[derive(Default)]
+    struct Vectored {
+        data: Vec<u8>,
+        calls: usize,
+    }
+
+    impl Write for Vectored {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.write_vectored(&[IoSlice::new(buf)])
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+            self.calls += 1;
+            bufs.iter().for_each(|buf| self.data.extend_from_slice(buf));
+            Ok(bufs.iter().map(|buf| buf.len()).sum())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn vectored_write_previous() -> Result<()> {
+        let data: Vec<u8> = (0..40000).map(|i| (i % 251) as u8).collect();
+        let mut writer = TrackingWriter::new(Vectored::default());
+        for chunk in data.chunks(1000) {
+            writer.write_all(chunk)?;
+        }
+        writer.get_mut().calls = 0;
+
+        writer.write_previous(30000, 20000)?;
+        assert_eq!(writer.get_ref().calls, 1);
+        let mut expected = data.clone();
+        expected.extend_from_slice(&data[10000..30000]);
+        assert_eq!(writer.get_ref().data, expected);
+        assert_eq!(writer.byte_count(), 60000);
+        assert_eq!(
+            writer.crc32(),
+            crate::checksum::ALGORITHM.checksum(&expected)
+        );
+        assert!(writer
+            .history()
+            .eq(expected[60000 - HISTORY_SIZE..].iter().copied()));
+        Ok(())
+    }
+
     #[test]
     fn write_previous() -> Result<()> {
         let mut buf: &mut [u8] = &mut [0u8; 512];
@@ -124,7 +352,14 @@ mod tests {
         writer.write_previous(192, 128)?;
         assert_eq!(writer.byte_count(), 384);
 
-        assert!(writer.write_previous(10000, 20).is_err());
+        let error = writer.write_previous(10000, 20).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::DistanceTooFar {
+                distance: 10000,
+                available: 384,
+            })
+        );
         assert_eq!(writer.byte_count(), 384);
 
         assert!(writer.write_previous(256, 256).is_err());
@@ -136,4 +371,72 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn write_previous_past_cap() -> Result<()> {
+        let mut expected: Vec<u8> = (0..HISTORY_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        let mut writer = TrackingWriter::new(Vec::new());
+        writer.write_all(&expected)?;
+
+        // Long overlapping runs that push far past the cap, and distances at
+        // or just below it, checked against a byte-by-byte copy.
+        for (dist, len) in [
+            (1, 40000),
+            (3, HISTORY_SIZE + 5),
+            (HISTORY_SIZE, 40000),
+            (HISTORY_SIZE - 1, 258),
+            (HISTORY_SIZE, HISTORY_SIZE),
+            (HISTORY_SIZE - 2, 3),
+        ] {
+            writer.write_previous(dist, len)?;
+            for _ in 0..len {
+                expected.push(expected[expected.len() - dist]);
+            }
+            assert_eq!(writer.get_ref(), &expected, "dist {} len {}", dist, len);
+            assert!(writer
+                .history()
+                .eq(expected[expected.len() - HISTORY_SIZE..].iter().copied()));
+        }
+        assert_eq!(
+            writer.crc32(),
+            crate::checksum::ALGORITHM.checksum(&expected)
+        );
+        assert!(writer.write_previous(HISTORY_SIZE + 1, 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn with_checksum() -> Result<()> {
+        let mut writer = TrackingWriter::with_checksum(Vec::new(), Adler32::default());
+        writer.write_all(b"Wiki")?;
+        writer.write_previous(4, 4)?;
+        assert_eq!(writer.checksum(), 0x0e04_0329);
+
+        writer.clear()?;
+        writer.write_all(b"Wikipedia")?;
+        assert_eq!(writer.checksum(), 0x11e6_0398);
+
+        let mut writer = TrackingWriter::with_checksum(Vec::new(), NoChecksum);
+        writer.write_all(b"Wikipedia")?;
+        assert_eq!(writer.checksum(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn reset() -> Result<()> {
+        let mut writer = TrackingWriter::new(Vec::new());
+        writer.write_all(&[1, 2, 3])?;
+        writer.write_previous(3, 3)?;
+
+        let old = writer.reset(Vec::new())?;
+        assert_eq!(old, [1, 2, 3, 1, 2, 3]);
+        assert_eq!(writer.byte_count(), 0);
+        assert_eq!(writer.crc32(), 0);
+        assert!(writer.write_previous(1, 1).is_err());
+
+        writer.write_all(&[4])?;
+        writer.write_previous(1, 2)?;
+        assert_eq!(writer.reset(Vec::new())?, [4, 4, 4]);
+        Ok(())
+    }
 }
@@ -0,0 +1,197 @@
+#![forbid(unsafe_code)]
+
+use std::io::{BufRead, Write};
+
+use anyhow::{bail, Context, Result};
+use byteorder::{BigEndian, ReadBytesExt};
+
+use crate::bit_reader::BitReader;
+use crate::checksum::{Adler32, Checksum};
+use crate::deflate::DeflateReader;
+use crate::tracking_writer::TrackingWriter;
+
+////////////////////////////////////////////////////////////////////////////////
+
+const CM_DEFLATE: u8 = 8;
+const CINFO_MAX: u8 = 7;
+
+const FDICT_OFFSET: u8 = 5;
+const FLEVEL_OFFSET: u8 = 6;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub struct ZlibHeader {
+    /// LZ77 window size in bytes, from CINFO.
+    pub window_size: u32,
+    /// FLEVEL: 0 (fastest) to 3 (maximum compression).
+    pub level: u8,
+    pub dictionary_id: Option<u32>,
+}
+
+/// Whether CMF and FLG form a valid zlib header for DEFLATE.
+pub fn is_zlib_header(cmf: u8, flg: u8) -> bool {
+    cmf & 0x0f == CM_DEFLATE
+        && cmf >> 4 <= CINFO_MAX
+        && (u16::from(cmf) << 8 | u16::from(flg)) % 31 == 0
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct ZlibReader<T> {
+    reader: T,
+}
+
+impl<T: BufRead> ZlibReader<T> {
+    pub fn new(reader: T) -> Self {
+        Self { reader }
+    }
+
+    pub fn parse_header(mut self) -> Result<ZlibHeader> {
+        let cmf = self.reader.read_u8().context("CMF")?;
+        let flg = self.reader.read_u8().context("FLG")?;
+        if !is_zlib_header(cmf, flg) {
+            bail!("wrong zlib header")
+        }
+
+        let mut dictionary_id = None;
+        if (flg >> FDICT_OFFSET) & 1 != 0 {
+            dictionary_id = Some(self.reader.read_u32::<BigEndian>().context("DICTID")?);
+        }
+
+        Ok(ZlibHeader {
+            window_size: 1 << (8 + (cmf >> 4)),
+            level: flg >> FLEVEL_OFFSET,
+            dictionary_id,
+        })
+    }
+
+    pub fn read_adler32(mut self) -> Result<u32> {
+        self.reader.read_u32::<BigEndian>().context("ADLER32")
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Decode a zlib (RFC 1950) stream. Streams that need a preset dictionary
+/// fail; see [`decompress_zlib_with_dictionary`].
+pub fn decompress_zlib<R: BufRead, W: Write>(input: R, output: W) -> Result<()> {
+    decompress_zlib_impl(input, output, None, None)
+}
+
+/// Decode a zlib stream compressed with a preset dictionary (FDICT). The
+/// dictionary must match the stream's DICTID. Only its last 32 KiB can be
+/// referenced, so a longer one is fine but only that tail is used.
+pub fn decompress_zlib_with_dictionary<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+    dictionary: &[u8],
+) -> Result<()> {
+    decompress_zlib_impl(input, output, Some(dictionary), None)
+}
+
+pub(crate) fn decompress_zlib_impl<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+    dictionary: Option<&[u8]>,
+    output_limit: Option<u64>,
+) -> Result<()> {
+    let mut deflate = DeflateReader::new(
+        BitReader::new(input),
+        TrackingWriter::with_checksum(output, Adler32::default()),
+    );
+    deflate.set_output_limit(output_limit);
+    let header = ZlibReader::new(deflate.get_input()).parse_header()?;
+    match (header.dictionary_id, dictionary) {
+        (None, _) => {}
+        (Some(id), None) => bail!(
+            "stream needs a preset dictionary (DICTID {:#010x}); \
+             use decompress_zlib_with_dictionary",
+            id
+        ),
+        (Some(id), Some(dictionary)) => {
+            let mut adler32 = Adler32::default();
+            adler32.update(dictionary);
+            if adler32.finalize() != id {
+                bail!("dictionary does not match DICTID {:#010x}", id);
+            }
+            deflate.set_dictionary(dictionary);
+        }
+    }
+    while !deflate.next_block()? {}
+    let adler32 = ZlibReader::new(deflate.get_input()).read_adler32()?;
+    deflate.check_adler32(adler32)?;
+    deflate.output()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_reader::BitSequence;
+    use crate::bit_writer::BitWriter;
+    use crate::decompress;
+    use crate::test_data::{FIRST, ZLIB};
+    use crate::DecodeError;
+
+    #[test]
+    fn zlib_header() -> Result<()> {
+        assert!(is_zlib_header(0x78, 0x9c));
+        assert!(is_zlib_header(0x78, 0x01));
+        assert!(!is_zlib_header(0x78, 0x9d));
+        assert!(!is_zlib_header(0x1f, 0x8b));
+
+        let header = ZlibReader::new(ZLIB).parse_header()?;
+        assert_eq!(header.window_size, 32768);
+        assert_eq!(header.level, 3);
+        assert!(header.dictionary_id.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let mut expected = Vec::new();
+        decompress(FIRST, &mut expected)?;
+
+        let mut output = Vec::new();
+        decompress_zlib(ZLIB, &mut output)?;
+        assert_eq!(output, expected);
+
+        let mut data = ZLIB.to_vec();
+        *data.last_mut().unwrap() ^= 1;
+        let error = decompress_zlib(data.as_slice(), Vec::new()).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<DecodeError>(),
+            Some(DecodeError::Adler32Mismatch { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn preset_dictionary() -> Result<()> {
+        // FDICT with DICTID = Adler-32 of "hello", then a fixed block copying
+        // length 5 from distance 5.
+        let mut writer = BitWriter::new(vec![0x78, 0xbb, 0x06, 0x2c, 0x02, 0x15]);
+        writer.write_bits(BitSequence::new(0b011, 3))?;
+        writer.write_bits(BitSequence::from_msb(0b0000011, 7))?;
+        writer.write_bits(BitSequence::from_msb(0b00100, 5))?;
+        writer.write_bits(BitSequence::new(0, 1))?;
+        writer.write_bits(BitSequence::from_msb(0, 7))?;
+        let mut data = writer.borrow_writer_from_boundary()?.clone();
+        data.extend([0x06, 0x2c, 0x02, 0x15]);
+
+        let mut output = Vec::new();
+        decompress_zlib_with_dictionary(data.as_slice(), &mut output, b"hello")?;
+        assert_eq!(output, b"hello");
+
+        let error = decompress_zlib(data.as_slice(), Vec::new()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "stream needs a preset dictionary (DICTID 0x062c0215); \
+             use decompress_zlib_with_dictionary"
+        );
+        assert!(decompress_zlib_with_dictionary(data.as_slice(), Vec::new(), b"jello").is_err());
+        Ok(())
+    }
+}